@@ -0,0 +1,150 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use log::debug;
+
+use crate::{
+    config::find_cargo_manifest,
+    error::{FileOperation, IOResultExt, ToolError, ToolResult},
+    utils::run_command,
+};
+
+#[derive(clap::Parser)]
+pub struct CargoArgs {
+    /// Target triple of NativeShell build to use when source path is not specified.
+    #[clap(long)]
+    target: Vec<String>,
+
+    // Cargo profile; Set from global --profile option
+    #[clap(skip)]
+    profile: Option<String>,
+}
+
+impl CargoArgs {
+    pub fn set_profile(&mut self, profile: Option<String>) {
+        self.profile = profile;
+    }
+
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    pub fn targets(&self) -> &[String] {
+        &self.target
+    }
+
+    // Returns app bundle produced by NativeShell for each specified target (or
+    // single bundle for host target if no target was specified).
+    pub fn find_app_bundles(&self) -> ToolResult<Vec<PathBuf>> {
+        let target_dir = target_directory()?;
+        if self.target.is_empty() {
+            let dir = target_dir.join(self.profile_dir());
+            Ok(vec![find_app_bundle(&dir)?])
+        } else {
+            self.target
+                .iter()
+                .map(|t| find_app_bundle(&target_dir.join(t).join(self.profile_dir())))
+                .collect()
+        }
+    }
+
+    // Folder for build artifacts combined from multiple targets
+    pub fn universal_dir(&self) -> ToolResult<PathBuf> {
+        Ok(target_directory()?
+            .join("universal-apple-darwin")
+            .join(self.profile_dir()))
+    }
+
+    fn profile_dir(&self) -> &str {
+        match self.profile.as_deref() {
+            None | Some("dev") | Some("test") => "debug",
+            Some("bench") => "release",
+            Some(profile) => profile,
+        }
+    }
+}
+
+// Returns cargo target directory for current workspace (honors CARGO_TARGET_DIR
+// and build.target-dir configuration).
+pub fn target_directory() -> ToolResult<PathBuf> {
+    let metadata = cargo_metadata()?;
+    match metadata.get("target_directory") {
+        Some(serde_json::Value::String(dir)) => {
+            debug!("Cargo target directory: {}", dir);
+            Ok(dir.into())
+        }
+        _ => Err(ToolError::OtherError(
+            "Cargo metadata is missing target directory".into(),
+        )),
+    }
+}
+
+// Returns version of package in current directory (with workspace inheritance
+// resolved), or None if not within a cargo package.
+pub fn package_version() -> ToolResult<Option<String>> {
+    let current_dir = std::env::current_dir().wrap_error(FileOperation::Read, || ".".into())?;
+    let manifest = match find_cargo_manifest(&current_dir) {
+        Some(manifest) => manifest,
+        None => return Ok(None),
+    };
+    let manifest = manifest.canonicalize().unwrap_or(manifest);
+    let metadata = cargo_metadata()?;
+    let packages = metadata.get("packages").and_then(|p| p.as_array());
+    let version = packages.into_iter().flatten().find_map(|package| {
+        let manifest_path = package.get("manifest_path")?.as_str()?;
+        if Path::new(manifest_path) == manifest {
+            package.get("version")?.as_str().map(|v| v.to_string())
+        } else {
+            None
+        }
+    });
+    debug!("Package version from {:?}: {:?}", manifest, version);
+    Ok(version)
+}
+
+fn cargo_metadata() -> ToolResult<serde_json::Value> {
+    let mut cmd = Command::new("cargo");
+    cmd.arg("metadata")
+        .arg("--format-version")
+        .arg("1")
+        .arg("--no-deps");
+    let metadata = run_command(cmd, "cargo")?.join("\n");
+    serde_json::from_str(&metadata)
+        .map_err(|e| ToolError::OtherError(format!("Malformed cargo metadata: {}", e)))
+}
+
+fn find_app_bundle(dir: &Path) -> ToolResult<PathBuf> {
+    if !dir.is_dir() {
+        return Err(ToolError::OtherError(format!(
+            "Build folder {:?} does not exist. Was the project built for this target and profile?",
+            dir
+        )));
+    }
+    let mut bundles = Vec::new();
+    for entry in dir
+        .read_dir()
+        .wrap_error(FileOperation::ReadDir, || dir.into())?
+    {
+        let entry = entry.wrap_error(FileOperation::Read, || dir.into())?;
+        let path = entry.path();
+        if path.extension().map(|e| e == "app").unwrap_or(false) && path.is_dir() {
+            bundles.push(path);
+        }
+    }
+    match bundles.len() {
+        1 => {
+            debug!("Found NativeShell bundle {:?}", bundles[0]);
+            Ok(bundles.remove(0))
+        }
+        0 => Err(ToolError::OtherError(format!(
+            "No app bundle found in {:?}",
+            dir
+        ))),
+        _ => Err(ToolError::OtherError(format!(
+            "Multiple app bundles found in {:?}: {:?}. Please specify the source path.",
+            dir, bundles
+        ))),
+    }
+}
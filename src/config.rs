@@ -0,0 +1,383 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use serde::Deserialize;
+
+use crate::error::{FileOperation, IOResultExt, ToolError, ToolResult};
+
+pub const CONFIG_FILE_NAME: &str = "bundle_tool.toml";
+
+//
+// Configuration is read from (first found):
+//   - path specified with --config
+//   - bundle_tool.toml in current directory
+//   - [package.metadata.bundle-tool] table in Cargo.toml of current crate
+//
+// Top level sections (bundle, codesign, notarize, universal, verify, lint) provide defaults for
+// respective subcommands. Sections within [profile.<name>] override these when the
+// profile is selected. Options specified on command line override both.
+//
+// Relative paths in configuration are resolved against the configuration file folder.
+//
+#[derive(Default)]
+pub struct Config {
+    base: ProfileConfig,
+    profile: HashMap<String, ProfileConfig>,
+}
+
+// Layout of configuration file; Top level sections are listed explicitly (instead of
+// flattening ProfileConfig) so that unknown sections are rejected.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
+struct ConfigFile {
+    bundle: BundleConfig,
+    codesign: CodesignConfig,
+    notarize: NotarizeConfig,
+    universal: UniversalConfig,
+    verify: VerifyConfig,
+    lint: LintConfig,
+    profile: HashMap<String, ProfileConfig>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub bundle: BundleConfig,
+    pub codesign: CodesignConfig,
+    pub notarize: NotarizeConfig,
+    pub universal: UniversalConfig,
+    pub verify: VerifyConfig,
+    pub lint: LintConfig,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
+pub struct BundleConfig {
+    pub source_path: Option<PathBuf>,
+    pub out_dir: Option<PathBuf>,
+    // Existing bundle updated by macos-plist
+    pub bundle_path: Option<PathBuf>,
+    pub delete_existing_bundle: Option<bool>,
+    pub bundle_name: Option<String>,
+    pub bundle_identifier: Option<String>,
+    pub bundle_version: Option<String>,
+    pub icon: Option<PathBuf>,
+    pub icon_pngs: Vec<PathBuf>,
+    pub icon_name: Option<String>,
+    pub minimum_system_version: Option<String>,
+    pub cargo_version: Option<bool>,
+    pub url_schemes: Vec<String>,
+    pub plist_fragments: Vec<PathBuf>,
+    // Keys set in Info.plist
+    pub info_plist: BTreeMap<String, toml::Value>,
+    pub strip_flutter_debug_assets: Option<bool>,
+    pub skip_flutter_checks: Option<bool>,
+    pub embed_swift_runtime: Option<bool>,
+    pub swift_toolchain: Option<PathBuf>,
+    pub strict_consistency: Option<bool>,
+    pub plugins: Vec<PathBuf>,
+    pub login_items: Vec<PathBuf>,
+    pub xpc_services: Vec<PathBuf>,
+    pub helpers: Vec<PathBuf>,
+    pub dependency_destinations: Vec<String>,
+    pub resources: Vec<ResourceConfig>,
+    // Runtime loaded libraries per executable (glob)
+    pub dlopen: BTreeMap<String, Vec<PathBuf>>,
+}
+
+// Resource glob (relative to configuration file) and folder within Contents/Resources
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ResourceConfig {
+    pub source: PathBuf,
+    pub destination: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
+pub struct CodesignConfig {
+    pub bundle_path: Option<PathBuf>,
+    pub entitlements: Vec<String>,
+    pub identity: Option<String>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
+pub struct NotarizeConfig {
+    pub bundle_path: Option<PathBuf>,
+    pub key: Option<String>,
+    pub key_id: Option<String>,
+    pub issuer: Option<String>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
+pub struct UniversalConfig {
+    pub paths_in: Vec<PathBuf>,
+    pub out: Option<PathBuf>,
+    pub delete_existing_bundle: Option<bool>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
+pub struct VerifyConfig {
+    pub bundle_path: Option<PathBuf>,
+    pub sdk_stubs: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "kebab-case", default, deny_unknown_fields)]
+pub struct LintConfig {
+    pub bundle_path: Option<PathBuf>,
+    pub strict: Option<bool>,
+}
+
+impl Config {
+    pub fn load(path: Option<&Path>) -> ToolResult<Self> {
+        if let Some(path) = path {
+            return Self::load_file(path);
+        }
+        let current_dir = std::env::current_dir().wrap_error(FileOperation::Read, || ".".into())?;
+        let config_file = current_dir.join(CONFIG_FILE_NAME);
+        if config_file.is_file() {
+            return Self::load_file(&config_file);
+        }
+        if let Some(manifest) = find_cargo_manifest(&current_dir) {
+            return Self::load_manifest(&manifest);
+        }
+        Ok(Self::default())
+    }
+
+    fn load_file(path: &Path) -> ToolResult<Self> {
+        debug!("Loading configuration from {:?}", path);
+        let value = read_toml(path)?;
+        Self::from_value(value, path)
+    }
+
+    fn load_manifest(path: &Path) -> ToolResult<Self> {
+        let manifest = read_toml(path)?;
+        let metadata = manifest
+            .get("package")
+            .and_then(|p| p.get("metadata"))
+            .and_then(|m| m.get("bundle-tool"));
+        match metadata {
+            Some(metadata) => {
+                debug!("Loading configuration from {:?}", path);
+                Self::from_value(metadata.clone(), path)
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    fn from_value(value: toml::Value, path: &Path) -> ToolResult<Self> {
+        let file: ConfigFile = value.try_into().map_err(|e| {
+            ToolError::OtherError(format!("Invalid configuration in {:?}: {}", path, e))
+        })?;
+        let mut config = Config {
+            base: ProfileConfig {
+                bundle: file.bundle,
+                codesign: file.codesign,
+                notarize: file.notarize,
+                universal: file.universal,
+                verify: file.verify,
+                lint: file.lint,
+            },
+            profile: file.profile,
+        };
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        config.base.resolve_paths(base_dir);
+        for profile in config.profile.values_mut() {
+            profile.resolve_paths(base_dir);
+        }
+        Ok(config)
+    }
+
+    // Returns configuration for given profile merged with top level configuration.
+    // --profile also selects cargo profile, so profile without configuration section
+    // uses top level configuration.
+    pub fn resolve(&self, profile: Option<&str>) -> ToolResult<ProfileConfig> {
+        match profile.and_then(|name| self.profile.get(name)) {
+            Some(config) => Ok(self.base.clone().merge(config.clone())),
+            None => {
+                if let Some(name) = profile {
+                    debug!("No configuration for profile \"{}\"; Using defaults", name);
+                }
+                Ok(self.base.clone())
+            }
+        }
+    }
+}
+
+// Fields set in `other` override fields in `self`
+macro_rules! merge_options {
+    ($self:ident, $other:ident, [$($field:ident),*], [$($list:ident),*]) => {
+        Self {
+            $($field: $other.$field.or($self.$field),)*
+            $($list: if $other.$list.is_empty() { $self.$list } else { $other.$list },)*
+        }
+    };
+}
+
+impl ProfileConfig {
+    fn merge(self, other: Self) -> Self {
+        Self {
+            bundle: self.bundle.merge(other.bundle),
+            codesign: self.codesign.merge(other.codesign),
+            notarize: self.notarize.merge(other.notarize),
+            universal: self.universal.merge(other.universal),
+            verify: self.verify.merge(other.verify),
+            lint: self.lint.merge(other.lint),
+        }
+    }
+
+    fn resolve_paths(&mut self, base_dir: &Path) {
+        for path in vec![
+            &mut self.bundle.source_path,
+            &mut self.bundle.out_dir,
+            &mut self.bundle.bundle_path,
+            &mut self.bundle.icon,
+            &mut self.bundle.swift_toolchain,
+            &mut self.codesign.bundle_path,
+            &mut self.notarize.bundle_path,
+            &mut self.universal.out,
+            &mut self.verify.bundle_path,
+            &mut self.verify.sdk_stubs,
+            &mut self.lint.bundle_path,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = base_dir.join(&path);
+        }
+        for path in self
+            .universal
+            .paths_in
+            .iter_mut()
+            .chain(self.bundle.icon_pngs.iter_mut())
+            .chain(self.bundle.plist_fragments.iter_mut())
+            .chain(self.bundle.resources.iter_mut().map(|r| &mut r.source))
+            .chain(self.bundle.plugins.iter_mut())
+            .chain(self.bundle.login_items.iter_mut())
+            .chain(self.bundle.xpc_services.iter_mut())
+            .chain(self.bundle.helpers.iter_mut())
+            .chain(self.bundle.dlopen.values_mut().flatten())
+        {
+            *path = base_dir.join(&path);
+        }
+    }
+}
+
+impl BundleConfig {
+    fn merge(self, other: Self) -> Self {
+        merge_options!(
+            self,
+            other,
+            [
+                source_path,
+                out_dir,
+                bundle_path,
+                delete_existing_bundle,
+                bundle_name,
+                bundle_identifier,
+                bundle_version,
+                icon,
+                icon_name,
+                minimum_system_version,
+                cargo_version,
+                strip_flutter_debug_assets,
+                skip_flutter_checks,
+                embed_swift_runtime,
+                swift_toolchain,
+                strict_consistency
+            ],
+            [
+                icon_pngs,
+                url_schemes,
+                plist_fragments,
+                info_plist,
+                plugins,
+                login_items,
+                xpc_services,
+                helpers,
+                dependency_destinations,
+                resources,
+                dlopen
+            ]
+        )
+    }
+}
+
+impl CodesignConfig {
+    fn merge(self, other: Self) -> Self {
+        merge_options!(self, other, [bundle_path, identity], [entitlements])
+    }
+}
+
+impl NotarizeConfig {
+    fn merge(self, other: Self) -> Self {
+        merge_options!(self, other, [bundle_path, key, key_id, issuer], [])
+    }
+}
+
+impl UniversalConfig {
+    fn merge(self, other: Self) -> Self {
+        merge_options!(self, other, [out, delete_existing_bundle], [paths_in])
+    }
+}
+
+impl VerifyConfig {
+    fn merge(self, other: Self) -> Self {
+        merge_options!(self, other, [bundle_path, sdk_stubs], [])
+    }
+}
+
+impl LintConfig {
+    fn merge(self, other: Self) -> Self {
+        merge_options!(self, other, [bundle_path, strict], [])
+    }
+}
+
+// Fills option that was not specified on command line from configuration
+pub fn apply<T: Clone>(option: &mut Option<T>, config: &Option<T>) {
+    if option.is_none() {
+        *option = config.clone();
+    }
+}
+
+// Resolves boolean flag that can be enabled (--flag) or disabled (--no-flag) on
+// command line; Configuration is only used when neither was specified.
+pub fn apply_flag(flag: &mut bool, negated: bool, config: Option<bool>) {
+    if negated {
+        *flag = false;
+    } else if !*flag {
+        *flag = config.unwrap_or(false);
+    }
+}
+
+// Returns value of option that must be specified either on command line or in configuration
+pub fn required<T: Clone>(option: &Option<T>, name: &str) -> ToolResult<T> {
+    option.clone().ok_or_else(|| {
+        ToolError::OtherError(format!(
+            "Missing {}; specify it on command line or in configuration",
+            name
+        ))
+    })
+}
+
+fn read_toml(path: &Path) -> ToolResult<toml::Value> {
+    let content = fs::read_to_string(path).wrap_error(FileOperation::Read, || path.into())?;
+    content
+        .parse::<toml::Value>()
+        .map_err(|e| ToolError::OtherError(format!("Failed to parse {:?}: {}", path, e)))
+}
+
+// Finds Cargo.toml of crate containing given folder
+pub fn find_cargo_manifest(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|d| d.join("Cargo.toml"))
+        .find(|p| p.is_file())
+}
@@ -24,6 +24,58 @@ pub enum FileOperation {
     Unarchive,
     Metadata,
 }
+
+#[derive(Debug)]
+pub struct HeaderPaddingIssue {
+    pub path: PathBuf,
+    pub arch: String,
+    pub missing_bytes: u64,
+}
+
+#[derive(Debug)]
+pub enum LinkProblem {
+    LibraryNotFound,
+    MissingArchitecture,
+    MissingSymbols(Vec<String>),
+}
+
+#[derive(Debug)]
+pub struct LinkIssue {
+    pub path: PathBuf,
+    pub arch: String,
+    pub library: String,
+    pub problem: LinkProblem,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LintFinding {
+    // Path relative to linted bundle
+    pub path: PathBuf,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Display for LintFinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {:?}: {}", self.severity, self.path, self.message)
+    }
+}
+
 #[derive(Debug)]
 pub enum ToolError {
     Command {
@@ -50,6 +102,9 @@ pub enum ToolError {
         log_file_url: Option<String>,
     },
     BundlesNotIdentical,
+    HeaderPadding(Vec<HeaderPaddingIssue>),
+    LinkIssues(Vec<LinkIssue>),
+    LintFindings(Vec<LintFinding>),
     OtherError(String),
 }
 
@@ -108,6 +163,51 @@ impl Display for ToolError {
             ToolError::BundlesNotIdentical => {
                 write!(f, "Bundles are not identical")
             }
+            ToolError::HeaderPadding(issues) => {
+                writeln!(
+                    f,
+                    "Not enough Mach-O header padding to change install names:"
+                )?;
+                for issue in issues {
+                    writeln!(
+                        f,
+                        "  {:?} ({}): {} bytes missing",
+                        issue.path, issue.arch, issue.missing_bytes
+                    )?;
+                }
+                write!(
+                    f,
+                    "Link affected binaries with -headerpad_max_install_names."
+                )
+            }
+            ToolError::LinkIssues(issues) => {
+                write!(f, "Bundle has unresolved dependencies:")?;
+                let mut last = None;
+                for issue in issues {
+                    if last != Some((&issue.path, &issue.arch)) {
+                        write!(f, "\n  {:?} ({}):", issue.path, issue.arch)?;
+                        last = Some((&issue.path, &issue.arch));
+                    }
+                    write!(f, "\n    {}: ", issue.library)?;
+                    match &issue.problem {
+                        LinkProblem::LibraryNotFound => write!(f, "library not found")?,
+                        LinkProblem::MissingArchitecture => {
+                            write!(f, "library is missing {} architecture", issue.arch)?
+                        }
+                        LinkProblem::MissingSymbols(symbols) => {
+                            write!(f, "missing symbols {}", symbols.join(", "))?
+                        }
+                    }
+                }
+                Ok(())
+            }
+            ToolError::LintFindings(findings) => {
+                write!(f, "Bundle lint failed:")?;
+                for finding in findings {
+                    write!(f, "\n  {}", finding)?;
+                }
+                Ok(())
+            }
         }
     }
 }
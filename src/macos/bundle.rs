@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
     fmt::Display,
     fs::{self},
     path::{Path, PathBuf},
@@ -7,39 +7,197 @@ use std::{
 };
 
 use log::{debug, trace};
+use scopeguard::defer;
 
 use crate::{
+    cargo::CargoArgs,
+    config::{apply, apply_flag, required, BundleConfig},
     error::{FileOperation, IOResultExt, ToolError, ToolResult},
-    utils::{copy, is_same, run_command},
+    utils::{glob_match, is_same, run_command, temp_dir, Copied, Copier, CopyArgs, StagingDir},
 };
 
-use super::utils::is_executable_binary;
+use super::{
+    consistency::{check_consistency, ConsistencyOptions},
+    dlopen::{report_dlopen_candidates, DlopenLibraries, DlopenOptions},
+    flutter::{check_flutter_content, FlutterOptions},
+    icon::{install_icon, IconOptions},
+    info_plist::{update_info_plist, InfoPlistOptions},
+    install_name::PlannedChanges,
+    native_assets::find_native_assets,
+    portability::BundleNames,
+    resources::{copy_resources, resource_targets, ResourceOptions},
+    skeleton::{create_skeleton, SkeletonOptions},
+    swift::{SwiftOptions, SwiftRuntime},
+    utils::{classify_binary, BinaryType},
+};
 
 #[derive(clap::Parser)]
 pub struct Options {
     /// Delete bundle in target directory (out-dir/BundleName.app) if already exists
-    #[clap(long)]
+    #[clap(long, overrides_with = "no_delete_existing_bundle")]
     delete_existing_bundle: bool,
-    /// Path to bundle produced by NativeShell
-    source_path: PathBuf,
-    /// Output directory
-    out_dir: PathBuf,
+
+    /// Keep existing bundle in target directory (overrides configuration)
+    #[clap(long, overrides_with = "delete_existing_bundle")]
+    no_delete_existing_bundle: bool,
+
+    /// Path to bundle produced by NativeShell (or to executable with --from-executable).
+    /// If omitted, the bundle is taken from configuration or located in cargo target
+    /// directory using --profile and --target.
+    source_path: Option<PathBuf>,
+
+    /// Output directory. When only one path is specified, it is the output directory.
+    out_dir: Option<PathBuf>,
+
+    /// Loadable plugin (.bundle or .plugin) to copy into Contents/PlugIns. Can be
+    /// specified multiple times.
+    #[clap(long)]
+    plugin: Vec<PathBuf>,
+
+    /// Login item helper app (.app) to copy into Contents/Library/LoginItems. Can be
+    /// specified multiple times.
+    #[clap(long)]
+    login_item: Vec<PathBuf>,
+
+    /// XPC service (.xpc) to copy into Contents/XPCServices. Can be specified
+    /// multiple times.
+    #[clap(long)]
+    xpc_service: Vec<PathBuf>,
+
+    /// Helper executable or app to copy into Contents/Helpers. Can be specified
+    /// multiple times.
+    #[clap(long)]
+    helper: Vec<PathBuf>,
+
+    /// Destination of embedded dependencies in form of <glob>=<folder>, i.e.
+    /// "libssl*.dylib=Contents/Libraries". Glob is matched against dependency name
+    /// (libFoo.dylib or Foo.framework) and first matching rule applies. Dependencies
+    /// without matching rule are copied to Contents/Frameworks.
+    #[clap(long)]
+    dependency_destination: Vec<String>,
+
+    #[clap(flatten)]
+    cargo: CargoArgs,
+
+    #[clap(flatten)]
+    copy: CopyArgs,
+
+    #[clap(flatten)]
+    skeleton: SkeletonOptions,
+
+    #[clap(flatten)]
+    info_plist: InfoPlistOptions,
+
+    #[clap(flatten)]
+    icon: IconOptions,
+
+    #[clap(flatten)]
+    resources: ResourceOptions,
+
+    #[clap(flatten)]
+    flutter: FlutterOptions,
+
+    #[clap(flatten)]
+    swift: SwiftOptions,
+
+    #[clap(flatten)]
+    consistency: ConsistencyOptions,
+
+    #[clap(flatten)]
+    dlopen: DlopenOptions,
+}
+
+impl Options {
+    pub fn apply_config(&mut self, config: &BundleConfig) {
+        // Single path argument is the output directory; This must be decided before
+        // merging configuration so that the meaning doesn't depend on it.
+        if self.out_dir.is_none() {
+            self.out_dir = self.source_path.take();
+        }
+        apply(&mut self.source_path, &config.source_path);
+        apply(&mut self.out_dir, &config.out_dir);
+        apply_flag(
+            &mut self.delete_existing_bundle,
+            self.no_delete_existing_bundle,
+            config.delete_existing_bundle,
+        );
+        if self.plugin.is_empty() {
+            self.plugin = config.plugins.clone();
+        }
+        if self.login_item.is_empty() {
+            self.login_item = config.login_items.clone();
+        }
+        if self.xpc_service.is_empty() {
+            self.xpc_service = config.xpc_services.clone();
+        }
+        if self.helper.is_empty() {
+            self.helper = config.helpers.clone();
+        }
+        if self.dependency_destination.is_empty() {
+            self.dependency_destination = config.dependency_destinations.clone();
+        }
+        self.skeleton.apply_config(config);
+        self.info_plist.apply_config(config);
+        self.icon.apply_config(config);
+        self.resources.apply_config(config);
+        self.flutter.apply_config(config);
+        self.swift.apply_config(config);
+        self.consistency.apply_config(config);
+        self.dlopen.apply_config(config);
+    }
+
+    pub fn set_profile(&mut self, profile: Option<String>) {
+        self.cargo.set_profile(profile);
+    }
+}
+
+#[derive(Clone)]
+struct Executable {
+    // Path in source bundle (or external plugin)
+    path: PathBuf,
+    // Resolved path of the binary
+    original: PathBuf,
+    // Path in target bundle
+    target: PathBuf,
+    // Plugins are loaded into host process, so their rpaths must be relative
+    // to the plugin binary
+    plugin: bool,
 }
 
 pub struct SelfContained {
     options: Options,
+    source_path: PathBuf,
     out_path: PathBuf,
-    executables: Vec<(PathBuf, PathBuf)>,
+    executables: Vec<Executable>,
     processed_libraries: HashMap<ModulePath, PathBuf>,
+    copier: Copier,
+    swift_runtime: Option<SwiftRuntime>,
+    changes: PlannedChanges,
+    // (glob, folder relative to bundle)
+    destination_rules: Vec<(String, PathBuf)>,
+    // Folders (relative to bundle) that received dependencies
+    destinations: BTreeSet<PathBuf>,
+    // Binaries that need rpaths to dependency folders
+    rpath_modules: Vec<(PathBuf, &'static str)>,
+    dlopen_libraries: Option<DlopenLibraries>,
 }
 
 impl SelfContained {
     pub fn new(options: Options) -> Self {
+        let copier = Copier::new((&options.copy).into());
         Self {
             options,
+            copier,
+            source_path: PathBuf::new(),
             out_path: PathBuf::new(),
             executables: Vec::new(),
             processed_libraries: HashMap::new(),
+            swift_runtime: None,
+            changes: PlannedChanges::default(),
+            destination_rules: Vec::new(),
+            destinations: BTreeSet::new(),
+            rpath_modules: Vec::new(),
+            dlopen_libraries: None,
         }
     }
 
@@ -48,16 +206,33 @@ impl SelfContained {
     //
     // The rough idea is a s follows:
     //
+    // 0. If source is a bare executable, generate bundle skeleton around it and use
+    //   that as source bundle. Check that file names of source bundle, embedded items
+    //   and resources are portable to macOS filesystems (no case or Unicode
+    //   normalization collisions, invalid UTF-8 or problematic characters) before
+    //   anything is copied.
+    //
     // 1. Recursively traverse files and folders in entire bundle and:
     //   If this is a Frameworks folder (either in main bundle or sub-bundles), skip it.
     //   If this is a symlink, preserve it if it is relative within bundle, resolve if it
     //     points out of bundle.
     //   If this is a folder, create matching one in target bundle.
     //   If this is a file, copy it.
+    //   Copy additional plugins, login items, XPC services and helpers into respective
+    //     bundle folders the same way.
+    //   Copy declared resources into Contents/Resources; Files colliding with source
+    //     bundle content are an error.
+    //   Update Info.plist of copied bundle (identifier, versions, custom keys).
+    //   Generate icon from PNG images if specified.
     //
-    // 2. For each copied executable:
-    //   Resolve dependencies (dylibs and frameworks). For each dependency:
-    //     If this is a system dependency do nothing.
+    // 2. For each copied executable (or plugin):
+    //   Resolve dependencies (dylibs and frameworks), including libraries declared
+    //   as loaded at runtime (--dlopen). For each dependency:
+    //   Embedded executables (outside Contents/MacOS) also resolve dependencies against
+    //     their own Frameworks folder and the host app Frameworks.
+    //     If this is a system dependency do nothing. With embed_swift_runtime Swift runtime
+    //       libraries are only considered system if macOS ships them at the deployment target
+    //       (as declared by the updated Info.plist).
     //     For local dependencies:
     //       If already processed, ignore.
     //       If dependency with same name but different content was already processed, fail.
@@ -65,53 +240,140 @@ impl SelfContained {
     //                      This would require additional work to ensure that the App.framework is kept
     //                      relative to containing bundle and not moved to top level bundle.
     //       Copy the dependency (either dylib or surrounding framework)
-    //         to top level bundle Contents/Frameworks folder
+    //         to top level bundle Contents/Frameworks folder (or folder specified
+    //         by first matching dependency destination rule)
     //       Change install name to @rpath/[dependency name]
     //       Change reference name in parent module to @rpath/[dependency name]
     //       Resolve all dependencies and continue recursively.
-    //    If executable has any local dependency, add rpath referring to main bundle
-    //      Frameworks folder.
+    //    If executable has any local dependency, add rpaths referring to main bundle
+    //      dependency folders relative to the executable location (relative to
+    //      @loader_path for plugins, as these may be loaded by any executable).
+    //   Install name and rpath changes are only collected at this point.
+    //
+    // 3. Verify that collected changes fit into header padding of each binary and
+    //   apply them.
+    //
+    // 4. Report string literals in bundled binaries that look like references to
+    //   libraries missing from the bundle (possibly loaded with dlopen).
+    //
+    // 5. Check that bundled binaries don't require newer macOS than the bundle declares
+    //   and contain all architectures of the main executable.
+    //
+    // 6. Check that bundled Flutter content has consistent build mode (and no debug
+    //   content in release profile).
     //
     pub fn perform(mut self) -> ToolResult<()> {
-        if !self.options.source_path.is_dir() {
+        let out_dir = required(&self.options.out_dir, "output directory")?;
+        self.destination_rules = self
+            .options
+            .dependency_destination
+            .iter()
+            .map(|rule| parse_destination_rule(rule))
+            .collect::<ToolResult<_>>()?;
+        self.dlopen_libraries = Some(DlopenLibraries::new(&self.options.dlopen)?);
+        self.source_path = match &self.options.source_path {
+            Some(source_path) => source_path.clone(),
+            None => self.find_source_bundle()?,
+        };
+
+        let skeleton_dir = if self.options.skeleton.enabled() {
+            Some(temp_dir()?)
+        } else {
+            None
+        };
+        defer! {
+            if let Some(skeleton_dir) = &skeleton_dir {
+                fs::remove_dir_all(skeleton_dir).ok();
+            }
+        }
+        if let Some(skeleton_dir) = &skeleton_dir {
+            self.source_path = create_skeleton(
+                &self.options.skeleton,
+                self.options.info_plist.bundle_identifier(),
+                &self.source_path,
+                skeleton_dir,
+            )?;
+        }
+
+        if !self.source_path.is_dir() {
             return Err(ToolError::OtherError(
                 "Source-path is not a valid folder.".into(),
             ));
         }
 
-        if !self.options.out_dir.is_dir() {
+        if !out_dir.is_dir() {
             return Err(ToolError::OtherError(
                 "Out-dir is not a valid folder".into(),
             ));
         }
 
-        self.out_path = self
-            .options
-            .out_dir
-            .join(self.options.source_path.file_name().unwrap());
-        if self.out_path.exists() {
-            if self.options.delete_existing_bundle {
-                fs::remove_dir_all(&self.out_path)
-                    .wrap_error(FileOperation::RemoveDir, || self.out_path.clone())?;
-            } else {
-                return Err(ToolError::OtherError(format!(
-                    "Target folder {:?} already exists. Please delete it first.",
-                    self.out_path
-                )));
-            }
+        let out_path = out_dir.join(self.source_path.file_name().unwrap());
+        if out_path.exists() && !self.options.delete_existing_bundle {
+            return Err(ToolError::OtherError(format!(
+                "Target folder {:?} already exists. Please delete it first.",
+                out_path
+            )));
         }
 
-        fs::create_dir(&self.out_path)
-            .wrap_error(FileOperation::MkDir, || self.out_path.clone())?;
+        self.check_input_names()?;
+
+        // Bundle is assembled in staging directory and only moved to out_path
+        // once complete; Existing bundle is kept until then.
+        let staging = StagingDir::new(&out_path)?;
+        self.out_path = staging.path().into();
+
+        self.process_dir(&self.source_path.clone(), &self.out_path.clone())?;
+        for kind in &[
+            Embedded::Plugin,
+            Embedded::LoginItem,
+            Embedded::XpcService,
+            Embedded::Helper,
+        ] {
+            self.process_embedded(*kind)?;
+        }
+        copy_resources(&self.options.resources, &self.out_path, &mut self.copier)?;
+        update_info_plist(&self.out_path, &self.options.info_plist)?;
+        if self.options.icon.enabled() {
+            install_icon(&self.out_path, &self.options.icon)?;
+        }
+        // Deployment target is read from the staged bundle, so that it reflects
+        // --minimum-system-version and other Info.plist changes
+        self.swift_runtime = SwiftRuntime::new(&self.options.swift, &self.out_path)?;
+
+        // Embedded executables are processed last so that they can use frameworks of
+        // the host app
+        let mut executables = self.executables.clone();
+        executables.sort_by_key(|e| e.plugin || self.is_embedded(e));
+        for executable in executables {
+            self.process_executable(&executable)?;
+        }
+        self.add_rpaths();
 
-        self.process_dir(&self.options.source_path.clone(), &self.out_path.clone())?;
+        self.changes.check_header_padding(&self.out_path)?;
+        self.changes.apply()?;
 
-        let executable = self.executables.clone();
-        for b in executable {
-            self.process_executable(&b.0, &b.1)?;
-        }
+        report_dlopen_candidates(&self.out_path)?;
 
-        Ok(())
+        check_consistency(&self.out_path, &self.options.consistency)?;
+
+        let release = self.options.cargo.profile() == Some("release");
+        check_flutter_content(&self.out_path, &self.options.flutter, release)?;
+
+        staging.commit(self.options.delete_existing_bundle)
+    }
+
+    fn find_source_bundle(&self) -> ToolResult<PathBuf> {
+        if self.options.skeleton.enabled() {
+            return Err(ToolError::OtherError(
+                "Executable path must be specified with --from-executable".into(),
+            ));
+        }
+        if self.options.cargo.targets().len() > 1 {
+            return Err(ToolError::OtherError(
+                "Only single target can be bundled at a time. Use macos-universal to combine multiple targets.".into(),
+            ));
+        }
+        Ok(self.options.cargo.find_app_bundles()?.remove(0))
     }
 
     fn process_dir(&mut self, src_dir: &Path, dst_dir: &Path) -> ToolResult<()> {
@@ -163,18 +425,38 @@ impl SelfContained {
                 fs::create_dir(&dest).wrap_error(FileOperation::CreateDir, || dest.clone())?;
                 debug!("{:?}: create directory", entry.path());
                 self.process_dir(&entry.path(), &dest)?;
+                self.copier
+                    .copy_metadata(&src_resolved, &dest)
+                    .wrap_error_with_src(FileOperation::Copy, || dest.clone(), || entry.path())?;
                 continue;
             } else {
-                fs::copy(&src_resolved, &dest).wrap_error_with_src(
-                    FileOperation::Copy,
-                    || dest.clone(),
-                    || entry.path(),
-                )?;
-                if !is_executable_binary(&src_resolved)? {
-                    debug!("{:?}: copy", entry.path());
+                let copied = self
+                    .copier
+                    .copy_file(&src_resolved, &dest)
+                    .wrap_error_with_src(FileOperation::Copy, || dest.clone(), || entry.path())?;
+                if copied == Copied::HardLink {
+                    // Hardlinked binary is processed through the first link
+                    debug!("{:?}: hardlink", entry.path());
                 } else {
-                    debug!("{:?}: copy binary", entry.path());
-                    self.executables.push((entry.path().clone(), src_resolved))
+                    match classify_binary(&src_resolved)? {
+                        Some(info) if info.binary_type.is_loadable() => {
+                            debug!("{:?}: copy binary", entry.path());
+                            let plugin = info.binary_type == BinaryType::Bundle
+                                || plugin_bundle(&dest, &self.out_path).is_some();
+                            self.executables.push(Executable {
+                                path: entry.path(),
+                                original: src_resolved,
+                                target: dest,
+                                plugin,
+                            })
+                        }
+                        Some(info) => {
+                            debug!("{:?}: copy {:?}", entry.path(), info.binary_type);
+                        }
+                        None => {
+                            debug!("{:?}: copy", entry.path());
+                        }
+                    }
                 }
                 continue;
             }
@@ -182,66 +464,211 @@ impl SelfContained {
         Ok(())
     }
 
-    fn process_executable(&mut self, executable: &Path, original: &Path) -> ToolResult<()> {
+    // Copies plugins, login items, XPC services or helpers specified on command line
+    // (or in configuration) into respective bundle folder
+    // Collects names of all inputs at their destination in bundle
+    fn check_input_names(&self) -> ToolResult<()> {
+        let mut names = BundleNames::default();
+        names.add_dir(&self.source_path, Path::new(""))?;
+        for kind in &[
+            Embedded::Plugin,
+            Embedded::LoginItem,
+            Embedded::XpcService,
+            Embedded::Helper,
+        ] {
+            for path in self.embedded_paths(*kind) {
+                if let Some(name) = path.file_name() {
+                    names.add(path, &kind.folder().join(name))?;
+                }
+            }
+        }
+        for (source, target) in resource_targets(&self.options.resources)? {
+            names.add(&source, &target)?;
+        }
+        names.check()
+    }
+
+    fn embedded_paths(&self, kind: Embedded) -> &[PathBuf] {
+        match kind {
+            Embedded::Plugin => &self.options.plugin,
+            Embedded::LoginItem => &self.options.login_item,
+            Embedded::XpcService => &self.options.xpc_service,
+            Embedded::Helper => &self.options.helper,
+        }
+    }
+
+    fn process_embedded(&mut self, kind: Embedded) -> ToolResult<()> {
+        let paths = self.embedded_paths(kind).to_vec();
+        if paths.is_empty() {
+            return Ok(());
+        }
+        let folder_path = self.out_path.join(kind.folder());
+        fs::create_dir_all(&folder_path)
+            .wrap_error(FileOperation::MkDir, || folder_path.clone())?;
+        for path in paths {
+            if !kind.is_valid(&path) {
+                return Err(ToolError::OtherError(format!(
+                    "{} {:?} is not {}",
+                    kind.name(),
+                    path,
+                    kind.description()
+                )));
+            }
+            let dest = folder_path.join(path.file_name().unwrap());
+            if dest.exists() {
+                return Err(ToolError::OtherError(format!(
+                    "{} {:?} already exists in bundle",
+                    kind.name(),
+                    dest.file_name().unwrap()
+                )));
+            }
+            debug!("{:?}: copy {}", path, kind.name().to_lowercase());
+            let original = path
+                .canonicalize()
+                .wrap_error(FileOperation::Canonicalize, || path.clone())?;
+            if original.is_dir() {
+                fs::create_dir(&dest).wrap_error(FileOperation::CreateDir, || dest.clone())?;
+                self.process_dir(&path, &dest)?;
+                self.copier
+                    .copy_metadata(&original, &dest)
+                    .wrap_error_with_src(FileOperation::Copy, || dest.clone(), || path.clone())?;
+            } else {
+                // Single file (MH_BUNDLE) plugin or helper executable
+                self.copier
+                    .copy_file(&original, &dest)
+                    .wrap_error_with_src(FileOperation::Copy, || dest.clone(), || path.clone())?;
+                self.executables.push(Executable {
+                    path,
+                    original,
+                    target: dest,
+                    plugin: kind == Embedded::Plugin,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Executables outside of Contents/MacOS (plugins, helpers, nested bundles)
+    fn is_embedded(&self, executable: &Executable) -> bool {
+        executable.target.parent() != Some(&self.out_path.join("Contents").join("MacOS"))
+    }
+
+    fn process_executable(&mut self, executable: &Executable) -> ToolResult<()> {
         debug!(
-            "Processing executable: {:?} (original {:?})",
-            executable, original
+            "Processing {}: {:?} (original {:?})",
+            if executable.plugin {
+                "plugin"
+            } else {
+                "executable"
+            },
+            executable.path,
+            executable.original
         );
-        let relative = pathdiff::diff_paths(executable, &self.options.source_path).unwrap();
-        let executable = executable
+        let path = executable
+            .path
             .canonicalize()
-            .wrap_error(FileOperation::Canonicalize, || executable.into())?;
-        let rpath = executable.parent().unwrap();
-        let path_resolver = PathResolver::new(vec![rpath]);
-        let module = load_executable(executable.clone(), original)?;
-
-        let target_executable_path = self.out_path.join(relative);
-        self.process_module(&target_executable_path, &module, &path_resolver)?;
-        let has_local_dependencies = module.dependencies.iter().any(|d| !d.is_system());
+            .wrap_error(FileOperation::Canonicalize, || executable.path.clone())?;
+        let mut search_paths = vec![path.parent().unwrap().to_path_buf()];
+        if executable.plugin || self.is_embedded(executable) {
+            // Embedded executable dependencies are resolved against its bundle's own
+            // Frameworks folder and the host app Frameworks
+            if let Some(embedded_bundle) = embedded_bundle(&executable.target, &self.out_path) {
+                let depth = executable
+                    .target
+                    .strip_prefix(&embedded_bundle)
+                    .unwrap()
+                    .components()
+                    .count();
+                if let Some(source_bundle) = executable.path.ancestors().nth(depth) {
+                    search_paths.push(source_bundle.join("Contents").join("Frameworks"));
+                }
+            }
+            search_paths.extend(self.destinations.iter().map(|d| self.out_path.join(d)));
+            search_paths.push(self.source_path.join("Contents").join("Frameworks"));
+        }
+        if let Some(swift_runtime) = &self.swift_runtime {
+            search_paths.extend(swift_runtime.search_paths().iter().cloned());
+        }
+        let path_resolver = PathResolver::new(search_paths.iter().map(|p| p.as_path()).collect());
+        let mut module = load_executable(path, &executable.original)?;
+        if let Some(dlopen_libraries) = &self.dlopen_libraries {
+            for library in dlopen_libraries.for_executable(&executable.target) {
+                debug!("Runtime loaded library {:?}", library);
+                module
+                    .dependencies
+                    .push(ModulePath(library.to_string_lossy().into()));
+            }
+        }
+
+        self.process_module(&executable.target, &module, &path_resolver)?;
+        let has_local_dependencies = module.dependencies.iter().any(|d| !self.is_system(d));
         if has_local_dependencies {
-            // Add rpath
-            let frameworks_path = self.out_path.join("Contents").join("Frameworks");
-            let rpath =
-                pathdiff::diff_paths(frameworks_path, target_executable_path.parent().unwrap())
-                    .unwrap();
-            let mut cmd = Command::new("install_name_tool");
-            cmd.arg("-add_rpath")
-                .arg(Path::new("@executable_path").join(rpath))
-                .arg(&target_executable_path);
-            run_command(cmd, "install_name_tool")?;
+            let base = if executable.plugin {
+                "@loader_path"
+            } else {
+                "@executable_path"
+            };
+            self.rpath_modules.push((executable.target.clone(), base));
         }
         Ok(())
     }
 
+    // Adds rpaths for all dependency folders; Dependencies may depend on libraries
+    // in other folders, which are resolved using rpaths of the executable.
+    fn add_rpaths(&mut self) {
+        for (module, base) in &self.rpath_modules {
+            for destination in &self.destinations {
+                let rpath =
+                    pathdiff::diff_paths(self.out_path.join(destination), module.parent().unwrap())
+                        .unwrap();
+                let rpath = Path::new(base).join(rpath);
+                self.changes
+                    .module(module)
+                    .rpaths
+                    .push(rpath.to_string_lossy().into());
+            }
+        }
+    }
+
+    // Folder (relative to bundle) where given dependency should be copied
+    fn destination(&self, root: &Path) -> PathBuf {
+        let name = root.file_name().unwrap().to_string_lossy();
+        self.destination_rules
+            .iter()
+            .find(|(glob, _)| glob_match(glob, &name))
+            .map(|(_, destination)| destination.clone())
+            .unwrap_or_else(|| Path::new("Contents").join("Frameworks"))
+    }
+
     fn process_module(
         &mut self,
         target_module_path: &Path,
         module: &Module,
         path_resolver: &PathResolver,
     ) -> ToolResult<()> {
-        let mut paths_to_change = Vec::<(ModulePath, ModulePath)>::new();
+        trace!("Processing dependencies of {:?}", module.path);
         for dependency in &module.dependencies {
-            if dependency.is_system() {
+            if self.is_system(dependency) {
                 continue;
             }
             let new_path = self.process_dependency(dependency, path_resolver)?;
             if &new_path != dependency {
-                paths_to_change.push((dependency.clone(), new_path));
+                self.changes
+                    .module(target_module_path)
+                    .changes
+                    .push((dependency.0.clone(), new_path.0));
             }
         }
-        if !paths_to_change.is_empty() {
-            debug!(
-                "Changing paths for {:?}: {:?}",
-                module.path, paths_to_change
-            );
-            let mut cmd = Command::new("install_name_tool");
-            for (from, to) in &paths_to_change {
-                cmd.arg("-change").arg(&from.0).arg(&to.0);
+        Ok(())
+    }
+
+    fn is_system(&self, dependency: &ModulePath) -> bool {
+        match &self.swift_runtime {
+            Some(swift_runtime) if dependency.is_swift_runtime() => {
+                swift_runtime.is_system(dependency)
             }
-            cmd.arg(target_module_path);
-            run_command(cmd, "install_name_tool")?;
+            _ => dependency.is_system(),
         }
-        Ok(())
     }
 
     fn process_dependency(
@@ -267,28 +694,28 @@ impl SelfContained {
             self.processed_libraries
                 .insert(new_module_path.clone(), resolved.clone());
             let library = load_library(resolved)?;
-            let frameworks_path = self.out_path.join("Contents").join("Frameworks");
-            fs::create_dir_all(&frameworks_path)
-                .wrap_error(FileOperation::MkDir, || frameworks_path.clone())?;
-            let copy_target = frameworks_path.join(root.file_name().unwrap());
+            let destination = self.destination(&root);
+            let destination_path = self.out_path.join(&destination);
+            fs::create_dir_all(&destination_path)
+                .wrap_error(FileOperation::MkDir, || destination_path.clone())?;
+            self.destinations.insert(destination);
+            let copy_target = destination_path.join(root.file_name().unwrap());
             let real_root = root
                 .canonicalize()
                 .wrap_error(FileOperation::Canonicalize, || root.clone())?;
 
-            copy(&real_root, &copy_target).wrap_error_with_src(
-                FileOperation::Copy,
-                || root.clone(),
-                || copy_target.clone(),
-            )?;
+            self.copier
+                .copy(&real_root, &copy_target)
+                .wrap_error_with_src(
+                    FileOperation::Copy,
+                    || root.clone(),
+                    || copy_target.clone(),
+                )?;
 
-            let target_module_path = frameworks_path.join(&relative_path);
+            let target_module_path = destination_path.join(&relative_path);
             self.process_module(&target_module_path, &library.module, path_resolver)?;
             if library.install_name != new_module_path {
-                let mut cmd = Command::new("install_name_tool");
-                cmd.arg("-id")
-                    .arg(&new_module_path.0)
-                    .arg(frameworks_path.join(&relative_path));
-                run_command(cmd, "install_name_tool")?;
+                self.changes.module(&target_module_path).id = Some(new_module_path.0.clone());
             }
         }
         Ok(new_module_path)
@@ -308,7 +735,15 @@ impl ModulePath {
             || self.0.starts_with("/lib/")
             || self.0.starts_with("/System/")
             // Ignore swift libraries except for libswift_Concurrency.dylib
-            || (self.0.starts_with("@rpath/libswift") && !self.0.ends_with("_Concurrency.dylib"))
+            || (self.is_swift_runtime() && !self.0.ends_with("_Concurrency.dylib"))
+    }
+
+    pub fn is_swift_runtime(&self) -> bool {
+        self.0.starts_with("@rpath/libswift")
+    }
+
+    pub fn file_name(&self) -> &str {
+        self.0.rsplit('/').next().unwrap_or(&self.0)
     }
 }
 
@@ -388,24 +823,10 @@ fn load_executable(path: PathBuf, original_path: &Path) -> ToolResult<Module> {
             path
         )))
     } else {
-        // This depends on behavior from NativeShell artifact emitter, which places all native assets
-        // inside a "native_assets" folder in the same directory as the executable.
-        let original_path_parent = original_path.parent().unwrap();
-        let native_assets_path = original_path_parent.join("native_assets");
-        if native_assets_path.exists() {
-            for file in fs::read_dir(&native_assets_path)
-                .wrap_error(FileOperation::ReadDir, || native_assets_path.clone())?
-            {
-                let file = file.wrap_error(FileOperation::Read, || native_assets_path.clone())?;
-                let path = file.path();
-                let Some(stem) = path.file_stem() else {
-                    continue;
-                };
-                let framework_binary = file.path().join("Versions").join("A").join(stem);
-                if framework_binary.exists() {
-                    paths.push(ModulePath(framework_binary.to_string_lossy().into()));
-                }
-            }
+        // Native assets are not linked to executable but loaded at runtime, so they
+        // are added as additional dependencies.
+        for asset in find_native_assets(original_path)? {
+            paths.push(ModulePath(asset.to_string_lossy().into()));
         }
 
         Ok(Module {
@@ -436,6 +857,102 @@ fn extract_module_path(line: String) -> ToolResult<ModulePath> {
     }
 }
 
+// Parses dependency destination rule in form of <glob>=<folder>
+fn parse_destination_rule(rule: &str) -> ToolResult<(String, PathBuf)> {
+    let (glob, destination) = rule.split_once('=').ok_or_else(|| {
+        ToolError::OtherError(format!(
+            "Invalid dependency destination \"{}\"; Expected <glob>=<folder>",
+            rule
+        ))
+    })?;
+    let destination = PathBuf::from(destination.trim_matches('/'));
+    let valid = destination.starts_with("Contents")
+        && destination
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)));
+    if !valid {
+        return Err(ToolError::OtherError(format!(
+            "Invalid dependency destination \"{}\"; Folder must be within bundle Contents",
+            rule
+        )));
+    }
+    Ok((glob.into(), destination))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Embedded {
+    Plugin,
+    LoginItem,
+    XpcService,
+    Helper,
+}
+
+impl Embedded {
+    fn name(&self) -> &'static str {
+        match self {
+            Embedded::Plugin => "Plugin",
+            Embedded::LoginItem => "Login item",
+            Embedded::XpcService => "XPC service",
+            Embedded::Helper => "Helper",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Embedded::Plugin => "a .bundle or .plugin",
+            Embedded::LoginItem => "an .app bundle",
+            Embedded::XpcService => "an .xpc bundle",
+            Embedded::Helper => "an executable or .app bundle",
+        }
+    }
+
+    // Folder within bundle
+    fn folder(&self) -> PathBuf {
+        let contents = Path::new("Contents");
+        match self {
+            Embedded::Plugin => contents.join("PlugIns"),
+            Embedded::LoginItem => contents.join("Library").join("LoginItems"),
+            Embedded::XpcService => contents.join("XPCServices"),
+            Embedded::Helper => contents.join("Helpers"),
+        }
+    }
+
+    fn is_valid(&self, path: &Path) -> bool {
+        let extension = path.extension().map(|e| e.to_string_lossy().to_string());
+        match (self, extension.as_deref()) {
+            (Embedded::Plugin, Some("bundle" | "plugin")) => path.exists(),
+            (Embedded::LoginItem, Some("app")) => path.is_dir(),
+            (Embedded::XpcService, Some("xpc")) => path.is_dir(),
+            (Embedded::Helper, Some("app")) => path.is_dir(),
+            (Embedded::Helper, _) => path.is_file(),
+            _ => false,
+        }
+    }
+}
+
+// Returns innermost .bundle or .plugin folder containing given path within root
+fn plugin_bundle(path: &Path, root: &Path) -> Option<PathBuf> {
+    innermost_bundle(path, root, &["bundle", "plugin"])
+}
+
+// Returns innermost nested bundle (plugin, app or XPC service) containing given path
+// within root
+fn embedded_bundle(path: &Path, root: &Path) -> Option<PathBuf> {
+    innermost_bundle(path, root, &["bundle", "plugin", "app", "xpc"])
+}
+
+fn innermost_bundle(path: &Path, root: &Path, extensions: &[&str]) -> Option<PathBuf> {
+    path.ancestors()
+        .skip(1)
+        .take_while(|p| p.starts_with(root) && *p != root)
+        .find(|p| {
+            p.extension()
+                .map(|e| extensions.iter().any(|x| e == *x))
+                .unwrap_or(false)
+        })
+        .map(|p| p.into())
+}
+
 fn find_dependency_root(path: &Path) -> PathBuf {
     if let Some(parent) = path.parent() {
         if parent
@@ -7,16 +7,17 @@ use std::{
 use log::debug;
 
 use crate::{
+    config::{apply, required, CodesignConfig},
     error::{FileOperation, IOResultExt, PlistResultExt, ToolError, ToolResult},
     utils::run_command,
 };
 
-use super::utils::is_executable_binary;
+use super::utils::classify_binary;
 
 #[derive(clap::Parser)]
 pub struct Options {
     /// Path to self-contained bundle produced by the macos_bundle command
-    bundle_path: PathBuf,
+    bundle_path: Option<PathBuf>,
 
     /// Path to the entitlements file. Multiple values in form of
     /// <bundle-id>:<path> are allowed.
@@ -25,11 +26,22 @@ pub struct Options {
 
     /// Identity used during the codesigning process
     #[clap(long)]
-    identity: String,
+    identity: Option<String>,
+}
+
+impl Options {
+    pub fn apply_config(&mut self, config: &CodesignConfig) {
+        apply(&mut self.bundle_path, &config.bundle_path);
+        apply(&mut self.identity, &config.identity);
+        if self.entitlements.is_empty() {
+            self.entitlements = config.entitlements.clone();
+        }
+    }
 }
 
 pub struct CodeSign {
     options: Options,
+    identity: String,
     done: HashSet<PathBuf>,
 }
 
@@ -37,12 +49,14 @@ impl CodeSign {
     pub fn new(options: Options) -> Self {
         Self {
             options,
+            identity: String::new(),
             done: HashSet::new(),
         }
     }
 
     pub fn perform(mut self) -> ToolResult<()> {
-        let bundle_path = self.options.bundle_path.clone();
+        let bundle_path = required(&self.options.bundle_path, "bundle path")?;
+        self.identity = required(&self.options.identity, "--identity")?;
         self.process_app_bundle(&bundle_path)
     }
 
@@ -72,11 +86,19 @@ impl CodeSign {
                 } else {
                     self.process_folder(path)?;
                 }
-            } else if is_executable_binary(path)? {
+            } else if let Some(info) = classify_binary(path)? {
+                // dSYM companions, object files and static libraries are not signed
+                if !info.binary_type.is_signable() {
+                    debug!("Skipping {:?} ({:?})", path, info.binary_type);
+                    continue;
+                }
                 // ignore bundle executables
                 if is_bundle_executable(path)? {
                     continue;
                 }
+                if info.signed {
+                    debug!("Replacing existing signature of {:?}", path);
+                }
                 self.codesign(path, false)?;
             }
         }
@@ -126,7 +148,7 @@ impl CodeSign {
         command
             .arg("-f")
             .arg("-s")
-            .arg(&self.options.identity)
+            .arg(&self.identity)
             .arg(&resolved);
         run_command(command, "codesign")?;
 
@@ -164,19 +186,25 @@ fn get_bundle_executable(info_plist: &Path) -> ToolResult<String> {
             return Ok(identifier.into());
         }
     }
-    Err(ToolError::OtherError("Malformed info.plist".into()))
+    Err(ToolError::OtherError(format!(
+        "{:?} is missing CFBundleExecutable string; Run macos-lint for details",
+        info_plist
+    )))
 }
 
 fn get_bundle_identifier(bundle_path: &Path) -> ToolResult<String> {
     let info_plist = bundle_path.join("Contents/Info.plist");
-    let plist = plist::Value::from_file(&info_plist).wrap_error(|| Some(info_plist))?;
+    let plist = plist::Value::from_file(&info_plist).wrap_error(|| Some(info_plist.clone()))?;
     if let plist::Value::Dictionary(plist) = plist {
         let identifier = plist.get("CFBundleIdentifier");
         if let Some(plist::Value::String(identifier)) = identifier {
             return Ok(identifier.into());
         }
     }
-    Err(ToolError::OtherError("Malformed info.plist".into()))
+    Err(ToolError::OtherError(format!(
+        "{:?} is missing CFBundleIdentifier string; Run macos-lint for details",
+        info_plist
+    )))
 }
 
 fn is_app_bundle(path: &Path) -> bool {
@@ -0,0 +1,129 @@
+use std::{collections::BTreeSet, path::Path};
+
+use log::{debug, warn};
+
+use crate::{
+    config::{apply_flag, BundleConfig},
+    error::{ToolError, ToolResult},
+};
+
+use super::{
+    macho::{MachFile, Version, MH_BUNDLE, MH_DYLIB, MH_EXECUTE},
+    utils::{find_binaries, info_plist_string},
+};
+
+// Apple Silicon is only supported since macOS 11, so arm64 slices can not target
+// older versions regardless of bundle deployment target.
+const ARM64_MINIMUM_OS: (u32, u32, u32) = (11, 0, 0);
+
+#[derive(clap::Parser)]
+pub struct ConsistencyOptions {
+    /// Fail when bundled binaries require newer macOS than LSMinimumSystemVersion or
+    /// lack architectures of the main executable (reported as warnings otherwise)
+    #[clap(long, overrides_with = "no_strict_consistency")]
+    strict_consistency: bool,
+
+    /// Report consistency problems as warnings (overrides configuration)
+    #[clap(long, overrides_with = "strict_consistency")]
+    no_strict_consistency: bool,
+}
+
+impl ConsistencyOptions {
+    pub fn apply_config(&mut self, config: &BundleConfig) {
+        apply_flag(
+            &mut self.strict_consistency,
+            self.no_strict_consistency,
+            config.strict_consistency,
+        );
+    }
+}
+
+//
+// Checks that every binary in the bundle:
+//   - doesn't require newer macOS than the bundle declares (LSMinimumSystemVersion, or
+//     minimum OS version of main executable if not specified)
+//   - contains all architectures of the main executable
+//
+pub fn check_consistency(bundle_path: &Path, options: &ConsistencyOptions) -> ToolResult<()> {
+    let info_plist = bundle_path.join("Contents").join("Info.plist");
+    let executable = match info_plist_string(&info_plist, "CFBundleExecutable")? {
+        Some(executable) => bundle_path.join("Contents").join("MacOS").join(executable),
+        None => return Ok(()),
+    };
+    let executable = match MachFile::read(&executable)? {
+        Some(executable) => executable,
+        None => return Ok(()),
+    };
+    let archs: BTreeSet<String> = executable.slices().map(|s| s.arch_name()).collect();
+    let declared = match info_plist_string(&info_plist, "LSMinimumSystemVersion")? {
+        Some(version) => Some(version.parse::<Version>()?),
+        None => executable
+            .slices()
+            .filter_map(|s| s.minimum_os_version())
+            .min(),
+    };
+    debug!(
+        "Bundle deployment target: {:?}, architectures: {:?}",
+        declared.map(|v| v.to_string()),
+        archs
+    );
+
+    let mut binaries = Vec::new();
+    find_binaries(bundle_path, &mut binaries)?;
+
+    let mut problems = Vec::<String>::new();
+    for binary in binaries {
+        let file = match MachFile::read(&binary)? {
+            Some(file) => file,
+            None => continue,
+        };
+        let relative = pathdiff::diff_paths(&binary, bundle_path).unwrap();
+        let mut binary_archs = BTreeSet::new();
+        for slice in file.slices() {
+            if !matches!(slice.file_type(), MH_EXECUTE | MH_DYLIB | MH_BUNDLE) {
+                continue;
+            }
+            let arch = slice.arch_name();
+            if let (Some(declared), Some(required)) = (declared, slice.minimum_os_version()) {
+                let effective = if arch.starts_with("arm64") {
+                    let (major, minor, patch) = ARM64_MINIMUM_OS;
+                    declared.max(Version::new(major, minor, patch))
+                } else {
+                    declared
+                };
+                if required > effective {
+                    problems.push(format!(
+                        "{:?} ({}) requires macOS {}, but bundle declares {}",
+                        relative, arch, required, declared
+                    ));
+                }
+            }
+            binary_archs.insert(arch);
+        }
+        if binary_archs.is_empty() {
+            continue;
+        }
+        let missing: Vec<_> = archs.difference(&binary_archs).cloned().collect();
+        if !missing.is_empty() {
+            problems.push(format!(
+                "{:?} is missing architectures: {}",
+                relative,
+                missing.join(", ")
+            ));
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else if options.strict_consistency {
+        Err(ToolError::OtherError(format!(
+            "Bundle consistency check failed:\n  {}",
+            problems.join("\n  ")
+        )))
+    } else {
+        for problem in problems {
+            warn!("{}", problem);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,426 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use serde::Serialize;
+
+use crate::{
+    config::required,
+    error::{FileOperation, IOResultExt, ToolError, ToolResult},
+    utils::is_same,
+};
+
+use super::{
+    inspect::{inspect_binary, MachInfo},
+    utils::{format_size, write_stdout},
+};
+
+#[derive(clap::Parser)]
+pub struct Options {
+    /// Bundle of previous release
+    old_bundle: Option<PathBuf>,
+
+    /// Bundle of new release
+    new_bundle: Option<PathBuf>,
+
+    /// Print differences as JSON
+    #[clap(long)]
+    json: bool,
+}
+
+pub struct Diff {
+    options: Options,
+}
+
+#[derive(PartialEq)]
+enum Entry {
+    File,
+    Symlink(PathBuf),
+}
+
+#[derive(Serialize, Default)]
+struct BundleDiff {
+    added: Vec<PathBuf>,
+    removed: Vec<PathBuf>,
+    modified: Vec<FileChange>,
+    frameworks: Vec<FrameworkChange>,
+}
+
+#[derive(Serialize)]
+struct FileChange {
+    path: PathBuf,
+    // Sizes are 0 for symlinks
+    old_size: u64,
+    new_size: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    plist: Vec<KeyChange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    macho: Option<MachChange>,
+}
+
+#[derive(Serialize)]
+struct KeyChange {
+    key: String,
+    old: Option<String>,
+    new: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Change<T> {
+    old: T,
+    new: T,
+}
+
+#[derive(Serialize)]
+struct MachChange {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    binary_type: Option<Change<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    archs: Option<Change<Vec<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    install_name: Option<Change<Option<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signed: Option<Change<bool>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    added_dependencies: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    removed_dependencies: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    added_rpaths: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    removed_rpaths: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct FrameworkChange {
+    path: PathBuf,
+    old_version: Option<String>,
+    new_version: Option<String>,
+}
+
+impl Diff {
+    pub fn new(options: Options) -> Self {
+        Self { options }
+    }
+
+    pub fn perform(self) -> ToolResult<()> {
+        let old_bundle = required(&self.options.old_bundle, "old bundle path")?;
+        let new_bundle = required(&self.options.new_bundle, "new bundle path")?;
+        for bundle in [&old_bundle, &new_bundle] {
+            if !bundle.is_dir() {
+                return Err(ToolError::OtherError(format!(
+                    "Bundle {:?} does not exist",
+                    bundle
+                )));
+            }
+        }
+        let diff = diff_bundles(&old_bundle, &new_bundle)?;
+        let out = if self.options.json {
+            let mut json = serde_json::to_string_pretty(&diff).map_err(|e| {
+                ToolError::OtherError(format!("Failed to serialize bundle diff: {}", e))
+            })?;
+            json.push('\n');
+            json
+        } else {
+            format_report(&diff)
+        };
+        write_stdout(&out)
+    }
+}
+
+fn diff_bundles(old_bundle: &Path, new_bundle: &Path) -> ToolResult<BundleDiff> {
+    let mut old_entries = BTreeMap::new();
+    collect_entries(old_bundle, old_bundle, &mut old_entries)?;
+    let mut new_entries = BTreeMap::new();
+    collect_entries(new_bundle, new_bundle, &mut new_entries)?;
+
+    let mut res = BundleDiff::default();
+    for (path, old_entry) in &old_entries {
+        let new_entry = match new_entries.get(path) {
+            Some(new_entry) => new_entry,
+            None => {
+                res.removed.push(path.clone());
+                continue;
+            }
+        };
+        let old_path = old_bundle.join(path);
+        let new_path = new_bundle.join(path);
+        let same = match (old_entry, new_entry) {
+            (Entry::File, Entry::File) => is_same(&old_path, &new_path)?,
+            (old_entry, new_entry) => old_entry == new_entry,
+        };
+        if same {
+            continue;
+        }
+        debug!("{:?}: modified", path);
+        let both_files = *old_entry == Entry::File && *new_entry == Entry::File;
+        let size = |path: &Path, entry: &Entry| -> ToolResult<u64> {
+            match entry {
+                Entry::File => Ok(path
+                    .metadata()
+                    .wrap_error(FileOperation::Metadata, || path.into())?
+                    .len()),
+                Entry::Symlink(_) => Ok(0),
+            }
+        };
+        let is_plist = path.extension().map(|e| e == "plist").unwrap_or(false);
+        res.modified.push(FileChange {
+            path: path.clone(),
+            old_size: size(&old_path, old_entry)?,
+            new_size: size(&new_path, new_entry)?,
+            plist: if both_files && is_plist {
+                diff_plists(&old_path, &new_path)
+            } else {
+                Vec::new()
+            },
+            macho: if both_files {
+                diff_binaries(&old_path, &new_path)?
+            } else {
+                None
+            },
+        });
+    }
+    res.added = new_entries
+        .keys()
+        .filter(|path| !old_entries.contains_key(*path))
+        .cloned()
+        .collect();
+
+    let old_frameworks = framework_versions(old_bundle, &old_entries);
+    let new_frameworks = framework_versions(new_bundle, &new_entries);
+    let frameworks: BTreeSet<_> = old_frameworks.keys().chain(new_frameworks.keys()).collect();
+    for framework in frameworks {
+        let old_version = old_frameworks.get(framework).cloned();
+        let new_version = new_frameworks.get(framework).cloned();
+        if old_version != new_version {
+            res.frameworks.push(FrameworkChange {
+                path: framework.clone(),
+                old_version: old_version.flatten(),
+                new_version: new_version.flatten(),
+            });
+        }
+    }
+    Ok(res)
+}
+
+// Collects files and symlinks (relative to bundle root) recursively; Symlinks are
+// not followed.
+fn collect_entries(
+    root: &Path,
+    dir: &Path,
+    entries: &mut BTreeMap<PathBuf, Entry>,
+) -> ToolResult<()> {
+    for entry in dir
+        .read_dir()
+        .wrap_error(FileOperation::ReadDir, || dir.into())?
+    {
+        let path = entry.wrap_error(FileOperation::Read, || dir.into())?.path();
+        let meta = path
+            .symlink_metadata()
+            .wrap_error(FileOperation::Metadata, || path.clone())?;
+        let relative = path.strip_prefix(root).unwrap().to_path_buf();
+        if meta.file_type().is_symlink() {
+            let target =
+                fs::read_link(&path).wrap_error(FileOperation::ReadLink, || path.clone())?;
+            entries.insert(relative, Entry::Symlink(target));
+        } else if meta.is_dir() {
+            collect_entries(root, &path, entries)?;
+        } else {
+            entries.insert(relative, Entry::File);
+        }
+    }
+    Ok(())
+}
+
+// Top level key changes; Nested values are compared as a whole.
+fn diff_plists(old_path: &Path, new_path: &Path) -> Vec<KeyChange> {
+    let read = |path: &Path| match plist::Value::from_file(path) {
+        Ok(plist::Value::Dictionary(dictionary)) => Some(dictionary),
+        Ok(_) => None,
+        Err(err) => {
+            debug!("{:?}: failed to read plist ({})", path, err);
+            None
+        }
+    };
+    let (old, new) = match (read(old_path), read(new_path)) {
+        (Some(old), Some(new)) => (old, new),
+        _ => return Vec::new(),
+    };
+    let keys: BTreeSet<_> = old.keys().chain(new.keys()).collect();
+    keys.into_iter()
+        .filter(|key| old.get(key) != new.get(key))
+        .map(|key| KeyChange {
+            key: key.clone(),
+            old: old.get(key).map(plist_value_string),
+            new: new.get(key).map(plist_value_string),
+        })
+        .collect()
+}
+
+fn plist_value_string(value: &plist::Value) -> String {
+    match value {
+        plist::Value::String(s) => s.clone(),
+        plist::Value::Boolean(b) => b.to_string(),
+        plist::Value::Integer(i) => i.to_string(),
+        plist::Value::Real(r) => r.to_string(),
+        plist::Value::Date(d) => d.to_xml_format(),
+        plist::Value::Array(a) => format!("[{} items]", a.len()),
+        plist::Value::Dictionary(d) => format!("{{{} keys}}", d.len()),
+        plist::Value::Data(d) => format!("<{} bytes>", d.len()),
+        _ => "?".into(),
+    }
+}
+
+fn diff_binaries(old_path: &Path, new_path: &Path) -> ToolResult<Option<MachChange>> {
+    let (old, new) = match (inspect_binary(old_path)?, inspect_binary(new_path)?) {
+        (Some(old), Some(new)) => (old, new),
+        _ => return Ok(None),
+    };
+    fn change<T: PartialEq + Clone>(old: &T, new: &T) -> Option<Change<T>> {
+        (old != new).then(|| Change {
+            old: old.clone(),
+            new: new.clone(),
+        })
+    }
+    let dependencies = |info: &MachInfo| -> Vec<String> {
+        info.dependencies.iter().map(|d| d.name.clone()).collect()
+    };
+    let (old_dependencies, new_dependencies) = (dependencies(&old), dependencies(&new));
+    Ok(Some(MachChange {
+        binary_type: change(&old.binary_type, &new.binary_type),
+        archs: change(&old.archs, &new.archs),
+        install_name: change(&old.install_name, &new.install_name),
+        signed: change(&old.signed, &new.signed),
+        added_dependencies: difference(&new_dependencies, &old_dependencies),
+        removed_dependencies: difference(&old_dependencies, &new_dependencies),
+        added_rpaths: difference(&new.rpaths, &old.rpaths),
+        removed_rpaths: difference(&old.rpaths, &new.rpaths),
+    }))
+}
+
+fn difference(a: &[String], b: &[String]) -> Vec<String> {
+    a.iter().filter(|s| !b.contains(s)).cloned().collect()
+}
+
+// Versions (CFBundleShortVersionString or CFBundleVersion) of frameworks in bundle,
+// keyed by framework path.
+fn framework_versions(
+    bundle: &Path,
+    entries: &BTreeMap<PathBuf, Entry>,
+) -> BTreeMap<PathBuf, Option<String>> {
+    let mut frameworks = BTreeSet::new();
+    for path in entries.keys() {
+        let mut prefix = PathBuf::new();
+        for component in path.components() {
+            prefix.push(component);
+            if prefix
+                .extension()
+                .map(|e| e == "framework")
+                .unwrap_or(false)
+            {
+                frameworks.insert(prefix.clone());
+            }
+        }
+    }
+    frameworks
+        .into_iter()
+        .map(|framework| {
+            let root = bundle.join(&framework);
+            let candidates = [
+                root.join("Resources").join("Info.plist"),
+                root.join("Versions/Current/Resources/Info.plist"),
+            ];
+            let info_plist = candidates.iter().find(|p| p.is_file());
+            let version = info_plist.and_then(|info_plist| {
+                let plist = plist::Value::from_file(info_plist).ok()?;
+                let dictionary = plist.as_dictionary()?;
+                ["CFBundleShortVersionString", "CFBundleVersion"]
+                    .iter()
+                    .find_map(|key| dictionary.get(key)?.as_string().map(String::from))
+            });
+            (framework, version)
+        })
+        .collect()
+}
+
+fn format_report(diff: &BundleDiff) -> String {
+    let mut out = String::new();
+    if diff.added.is_empty() && diff.removed.is_empty() && diff.modified.is_empty() {
+        out.push_str("Bundles are identical\n");
+        return out;
+    }
+    for path in &diff.added {
+        out.push_str(&format!("+ {}\n", path.display()));
+    }
+    for path in &diff.removed {
+        out.push_str(&format!("- {}\n", path.display()));
+    }
+    for change in &diff.modified {
+        let delta = change.new_size as i64 - change.old_size as i64;
+        out.push_str(&format!(
+            "M {} ({} -> {}, {}{})\n",
+            change.path.display(),
+            format_size(change.old_size),
+            format_size(change.new_size),
+            if delta < 0 { "-" } else { "+" },
+            format_size(delta.unsigned_abs())
+        ));
+        for key in &change.plist {
+            out.push_str(&format!(
+                "    {}: {} -> {}\n",
+                key.key,
+                key.old.as_deref().unwrap_or("(none)"),
+                key.new.as_deref().unwrap_or("(none)")
+            ));
+        }
+        if let Some(macho) = &change.macho {
+            if let Some(c) = &macho.binary_type {
+                out.push_str(&format!("    type: {} -> {}\n", c.old, c.new));
+            }
+            if let Some(c) = &macho.archs {
+                out.push_str(&format!(
+                    "    archs: [{}] -> [{}]\n",
+                    c.old.join(", "),
+                    c.new.join(", ")
+                ));
+            }
+            if let Some(c) = &macho.install_name {
+                out.push_str(&format!(
+                    "    install name: {} -> {}\n",
+                    c.old.as_deref().unwrap_or("(none)"),
+                    c.new.as_deref().unwrap_or("(none)")
+                ));
+            }
+            if let Some(c) = &macho.signed {
+                out.push_str(&format!("    signed: {} -> {}\n", c.old, c.new));
+            }
+            for d in &macho.added_dependencies {
+                out.push_str(&format!("    + dependency {}\n", d));
+            }
+            for d in &macho.removed_dependencies {
+                out.push_str(&format!("    - dependency {}\n", d));
+            }
+            for r in &macho.added_rpaths {
+                out.push_str(&format!("    + rpath {}\n", r));
+            }
+            for r in &macho.removed_rpaths {
+                out.push_str(&format!("    - rpath {}\n", r));
+            }
+        }
+    }
+    if !diff.frameworks.is_empty() {
+        out.push_str("\nFrameworks:\n");
+        for framework in &diff.frameworks {
+            out.push_str(&format!(
+                "  {}: {} -> {}\n",
+                framework.path.display(),
+                framework.old_version.as_deref().unwrap_or("(none)"),
+                framework.new_version.as_deref().unwrap_or("(none)")
+            ));
+        }
+    }
+    out
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    path::{Path, PathBuf},
+};
+
+use log::{debug, warn};
+
+use crate::{
+    config::BundleConfig,
+    error::{ToolError, ToolResult},
+    utils::glob_match,
+};
+
+use super::{macho::MachFile, native_assets::framework_binary, utils::find_binaries};
+
+#[derive(clap::Parser)]
+pub struct DlopenOptions {
+    /// Library or framework loaded at runtime (dlopen) to bundle, in form of
+    /// [<executable>=]<path>, i.e. "MyApp=libs/libplugin.dylib". Executable name can
+    /// be a glob; Without it the library is bundled for every executable. Can be
+    /// specified multiple times.
+    #[clap(long, parse(from_str = parse_dlopen))]
+    dlopen: Vec<(String, PathBuf)>,
+}
+
+// "[<executable>=]<path>" -> (executable glob, path)
+fn parse_dlopen(value: &str) -> (String, PathBuf) {
+    match value.split_once('=') {
+        Some((executable, path)) => (executable.into(), path.into()),
+        None => ("*".into(), value.into()),
+    }
+}
+
+impl DlopenOptions {
+    pub fn apply_config(&mut self, config: &BundleConfig) {
+        if self.dlopen.is_empty() {
+            self.dlopen = config
+                .dlopen
+                .iter()
+                .flat_map(|(executable, paths)| {
+                    paths
+                        .iter()
+                        .map(move |path| (executable.clone(), path.clone()))
+                })
+                .collect();
+        }
+    }
+}
+
+// Libraries loaded at runtime; These never appear in load commands so they must be
+// declared explicitly.
+pub struct DlopenLibraries {
+    // (executable glob, library binary)
+    libraries: Vec<(String, PathBuf)>,
+}
+
+impl DlopenLibraries {
+    pub fn new(options: &DlopenOptions) -> ToolResult<Self> {
+        let libraries = options
+            .dlopen
+            .iter()
+            .map(|(executable, path)| Ok((executable.clone(), library_binary(path)?)))
+            .collect::<ToolResult<_>>()?;
+        Ok(Self { libraries })
+    }
+
+    // Libraries declared for executable at given path
+    pub fn for_executable(&self, executable: &Path) -> Vec<PathBuf> {
+        let name = executable.file_name().unwrap().to_string_lossy();
+        self.libraries
+            .iter()
+            .filter(|(glob, _)| glob_match(glob, &name))
+            .map(|(_, path)| path.clone())
+            .collect()
+    }
+}
+
+// Returns binary for dylib or framework path
+fn library_binary(path: &Path) -> ToolResult<PathBuf> {
+    let binary = if path.extension().map(|e| e == "framework").unwrap_or(false) {
+        framework_binary(path)?
+    } else if path.is_file() {
+        Some(path.into())
+    } else {
+        None
+    };
+    binary.ok_or_else(|| {
+        ToolError::OtherError(format!("Runtime loaded library {:?} not found", path))
+    })
+}
+
+//
+// Scans __cstring sections of bundle binaries for .dylib and .framework literals
+// that don't refer to system libraries or anything present in the bundle. These are
+// likely libraries loaded with dlopen that need to be declared with --dlopen.
+//
+pub fn report_dlopen_candidates(bundle_path: &Path) -> ToolResult<()> {
+    let mut binaries = Vec::new();
+    find_binaries(bundle_path, &mut binaries)?;
+
+    let mut bundled = HashSet::new();
+    for binary in &binaries {
+        let relative = binary.strip_prefix(bundle_path).unwrap();
+        for component in relative.components() {
+            let name = component.as_os_str().to_string_lossy();
+            if name.ends_with(".framework") || name.ends_with(".dylib") {
+                bundled.insert(name.to_string());
+            }
+        }
+    }
+
+    for binary in &binaries {
+        let candidates: BTreeSet<_> = library_literals(binary)?
+            .into_iter()
+            .filter(|literal| match library_name(literal) {
+                Some(name) => !bundled.contains(name),
+                None => false,
+            })
+            .collect();
+        for candidate in candidates {
+            warn!(
+                "{:?} references \"{}\" which is not in bundle; If it is loaded at runtime, declare it with --dlopen",
+                binary.strip_prefix(bundle_path).unwrap(),
+                candidate
+            );
+        }
+    }
+    Ok(())
+}
+
+// Name of the library (libfoo.dylib or Foo.framework) referenced by string literal;
+// None for system libraries and literals that are not plain paths.
+fn library_name(literal: &str) -> Option<&str> {
+    if literal.starts_with("/usr/lib/")
+        || literal.starts_with("/System/")
+        || literal.contains(|c: char| c.is_whitespace() || c == '%')
+    {
+        return None;
+    }
+    let mut components = literal.split('/');
+    match components.clone().find(|c| c.ends_with(".framework")) {
+        Some(framework) => Some(framework),
+        None => components.next_back().filter(|c| c.ends_with(".dylib")),
+    }
+}
+
+fn library_literals(binary: &Path) -> ToolResult<Vec<String>> {
+    let file = match MachFile::read(binary)? {
+        Some(file) => file,
+        None => return Ok(Vec::new()),
+    };
+    let mut res = Vec::new();
+    for slice in file.slices() {
+        for section in slice.sections() {
+            if section.segment_name != "__TEXT" || section.section_name != "__cstring" {
+                continue;
+            }
+            let start = section.offset as usize;
+            // Section header comes from the file, so the range is checked
+            let data = match start
+                .checked_add(section.size as usize)
+                .and_then(|end| slice.data().get(start..end))
+            {
+                Some(data) => data,
+                None => continue,
+            };
+            for string in data.split(|b| *b == 0) {
+                let string = match std::str::from_utf8(string) {
+                    Ok(string) => string,
+                    Err(_) => continue,
+                };
+                if string.ends_with(".dylib") || string.contains(".framework") {
+                    res.push(string.into());
+                }
+            }
+        }
+    }
+    debug!("Library literals in {:?}: {:?}", binary, res);
+    Ok(res)
+}
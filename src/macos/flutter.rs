@@ -0,0 +1,221 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info, warn};
+
+use crate::{
+    config::{apply_flag, BundleConfig},
+    error::{FileOperation, IOResultExt, ToolError, ToolResult},
+};
+
+use super::utils::file_contains;
+
+// Assets in flutter_assets that are only used by JIT (debug) builds
+const DEBUG_ONLY_ASSETS: &[&str] = &[
+    "kernel_blob.bin",
+    "vm_snapshot_data",
+    "isolate_snapshot_data",
+];
+
+// Symbol exported from AOT compiled App.framework
+const AOT_SNAPSHOT_SYMBOL: &[u8] = b"kDartIsolateSnapshotInstructions";
+
+// Name of kernel service isolate, which is only compiled into JIT (debug) engine
+const JIT_ENGINE_MARKER: &[u8] = b"kernel-service";
+
+#[derive(clap::Parser)]
+pub struct FlutterOptions {
+    /// Remove debug-only assets (kernel_blob.bin, snapshot data) from AOT compiled Flutter app
+    #[clap(long, overrides_with = "no_strip_flutter_debug_assets")]
+    strip_flutter_debug_assets: bool,
+
+    /// Keep debug-only assets of Flutter app (overrides configuration)
+    #[clap(long, overrides_with = "strip_flutter_debug_assets")]
+    no_strip_flutter_debug_assets: bool,
+
+    /// Do not check Flutter build mode of bundled frameworks
+    #[clap(long, overrides_with = "no_skip_flutter_checks")]
+    skip_flutter_checks: bool,
+
+    /// Check Flutter build mode of bundled frameworks (overrides configuration)
+    #[clap(long, overrides_with = "skip_flutter_checks")]
+    no_skip_flutter_checks: bool,
+}
+
+impl FlutterOptions {
+    pub fn apply_config(&mut self, config: &BundleConfig) {
+        apply_flag(
+            &mut self.strip_flutter_debug_assets,
+            self.no_strip_flutter_debug_assets,
+            config.strip_flutter_debug_assets,
+        );
+        apply_flag(
+            &mut self.skip_flutter_checks,
+            self.no_skip_flutter_checks,
+            config.skip_flutter_checks,
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BuildMode {
+    // JIT
+    Debug,
+    // AOT (profile or release)
+    Precompiled,
+}
+
+impl Display for BuildMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildMode::Debug => write!(f, "debug"),
+            BuildMode::Precompiled => write!(f, "profile/release"),
+        }
+    }
+}
+
+//
+// Checks Flutter content of the bundle:
+//   - build mode of each App.framework must match build mode of FlutterMacOS.framework
+//   - AOT compiled App.framework must not contain debug-only assets (these are removed
+//     when strip_flutter_debug_assets is set)
+//   - in release profile, all Flutter content must be AOT compiled
+//
+pub fn check_flutter_content(
+    bundle_path: &Path,
+    options: &FlutterOptions,
+    release: bool,
+) -> ToolResult<()> {
+    if options.skip_flutter_checks {
+        return Ok(());
+    }
+    let mut engines = Vec::new();
+    let mut apps = Vec::new();
+    find_frameworks(bundle_path, &mut engines, &mut apps)?;
+
+    let mut problems = Vec::<String>::new();
+
+    let relative = |path: &Path| pathdiff::diff_paths(path, bundle_path).unwrap();
+
+    let mut engine_modes = Vec::new();
+    for engine in &engines {
+        let mode = engine_build_mode(engine)?;
+        debug!("{:?}: {} engine", engine, mode);
+        engine_modes.push(mode);
+        if release && mode == BuildMode::Debug {
+            problems.push(format!(
+                "{:?} is a debug build of Flutter engine",
+                relative(engine)
+            ));
+        }
+    }
+
+    for app in &apps {
+        let mode = app_build_mode(app)?;
+        debug!("{:?}: {} app", app, mode);
+        if release && mode == BuildMode::Debug {
+            problems.push(format!("{:?} is a debug (JIT) build", relative(app)));
+        }
+        if let Some(engine_mode) = engine_modes.iter().find(|m| **m != mode) {
+            return Err(ToolError::OtherError(format!(
+                "Flutter build mode mismatch: {:?} is {} build, but engine is {} build",
+                relative(app),
+                mode,
+                engine_mode
+            )));
+        }
+        if mode == BuildMode::Precompiled {
+            let assets = debug_only_assets(app);
+            if options.strip_flutter_debug_assets {
+                for asset in assets {
+                    info!("Removing debug-only asset {:?}", relative(&asset));
+                    fs::remove_file(&asset).wrap_error(FileOperation::Remove, || asset)?;
+                }
+            } else if !assets.is_empty() {
+                let assets: Vec<_> = assets.iter().map(|a| relative(a)).collect();
+                problems.push(format!(
+                    "{:?} contains debug-only assets: {:?}",
+                    relative(app),
+                    assets
+                ));
+            }
+        } else if options.strip_flutter_debug_assets {
+            warn!(
+                "Not stripping debug assets from {:?}; they are required by debug build",
+                relative(app)
+            );
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else if release {
+        Err(ToolError::OtherError(format!(
+            "Bundle contains Flutter debug content:\n  {}",
+            problems.join("\n  ")
+        )))
+    } else {
+        for problem in problems {
+            warn!("{}", problem);
+        }
+        Ok(())
+    }
+}
+
+fn find_frameworks(
+    dir: &Path,
+    engines: &mut Vec<PathBuf>,
+    apps: &mut Vec<PathBuf>,
+) -> ToolResult<()> {
+    for entry in dir
+        .read_dir()
+        .wrap_error(FileOperation::ReadDir, || dir.into())?
+    {
+        let entry = entry.wrap_error(FileOperation::Read, || dir.into())?;
+        let meta = entry
+            .path()
+            .symlink_metadata()
+            .wrap_error(FileOperation::MetaData, || entry.path())?;
+        if !meta.is_dir() {
+            continue;
+        }
+        if entry.file_name() == "FlutterMacOS.framework" {
+            engines.push(entry.path());
+        } else if entry.file_name() == "App.framework" {
+            apps.push(entry.path());
+        } else {
+            find_frameworks(&entry.path(), engines, apps)?;
+        }
+    }
+    Ok(())
+}
+
+fn engine_build_mode(framework: &Path) -> ToolResult<BuildMode> {
+    let binary = framework.join("FlutterMacOS");
+    if file_contains(&binary, JIT_ENGINE_MARKER)? {
+        Ok(BuildMode::Debug)
+    } else {
+        Ok(BuildMode::Precompiled)
+    }
+}
+
+fn app_build_mode(framework: &Path) -> ToolResult<BuildMode> {
+    let binary = framework.join("App");
+    if binary.is_file() && file_contains(&binary, AOT_SNAPSHOT_SYMBOL)? {
+        Ok(BuildMode::Precompiled)
+    } else {
+        Ok(BuildMode::Debug)
+    }
+}
+
+fn debug_only_assets(app_framework: &Path) -> Vec<PathBuf> {
+    let flutter_assets = app_framework.join("Resources").join("flutter_assets");
+    DEBUG_ONLY_ASSETS
+        .iter()
+        .map(|a| flutter_assets.join(a))
+        .filter(|a| a.is_file())
+        .collect()
+}
@@ -0,0 +1,400 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, warn};
+
+use crate::{
+    config::{apply, required, BundleConfig},
+    error::{FileOperation, IOResultExt, ToolError, ToolResult},
+};
+
+use super::info_plist::set_info_plist_value;
+
+// ICNS element types with PNG payload and their pixel size; Retina (@2x) variants
+// share pixel size with the next larger regular variant.
+const ICNS_TYPES: &[(&[u8; 4], u32)] = &[
+    (b"icp4", 16),
+    (b"ic11", 32), // 16x16@2x
+    (b"icp5", 32),
+    (b"ic12", 64), // 32x32@2x
+    (b"ic07", 128),
+    (b"ic13", 256), // 128x128@2x
+    (b"ic08", 256),
+    (b"ic14", 512), // 256x256@2x
+    (b"ic09", 512),
+    (b"ic10", 1024), // 512x512@2x
+];
+
+#[derive(clap::Parser)]
+pub struct IconOptions {
+    /// Square PNG used to generate the bundle icon (.icns). Either specify a single
+    /// large (1024x1024) image, or multiple images of different sizes; Missing sizes
+    /// are scaled down from the closest larger image.
+    #[clap(long)]
+    icon_png: Vec<PathBuf>,
+
+    /// Name of generated icon file in Contents/Resources (without extension)
+    #[clap(long)]
+    icon_name: Option<String>,
+}
+
+impl IconOptions {
+    pub fn apply_config(&mut self, config: &BundleConfig) {
+        if self.icon_png.is_empty() {
+            self.icon_png = config.icon_pngs.clone();
+        }
+        apply(&mut self.icon_name, &config.icon_name);
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.icon_png.is_empty()
+    }
+}
+
+#[derive(clap::Parser)]
+pub struct Options {
+    /// Bundle to install the icon into, or path of .icns file to generate
+    out: Option<PathBuf>,
+
+    #[clap(flatten)]
+    icon: IconOptions,
+}
+
+impl Options {
+    pub fn apply_config(&mut self, config: &BundleConfig) {
+        self.icon.apply_config(config);
+    }
+}
+
+pub struct Icon {
+    options: Options,
+}
+
+impl Icon {
+    pub fn new(options: Options) -> Self {
+        Self { options }
+    }
+
+    pub fn perform(self) -> ToolResult<()> {
+        let out = required(&self.options.out, "output path")?;
+        if !self.options.icon.enabled() {
+            return Err(ToolError::OtherError(
+                "No source image; Specify it with --icon-png".into(),
+            ));
+        }
+        if out.extension().map(|e| e == "icns").unwrap_or(false) {
+            create_icns(&self.options.icon.icon_png, &out)
+        } else {
+            install_icon(&out, &self.options.icon)
+        }
+    }
+}
+
+// Generates icon into Contents/Resources of given bundle and sets CFBundleIconFile
+pub fn install_icon(bundle_path: &Path, options: &IconOptions) -> ToolResult<()> {
+    let name = options.icon_name.as_deref().unwrap_or("AppIcon");
+    let resources = bundle_path.join("Contents").join("Resources");
+    fs::create_dir_all(&resources).wrap_error(FileOperation::MkDir, || resources.clone())?;
+    let icns = resources.join(format!("{}.icns", name));
+    create_icns(&options.icon_png, &icns)?;
+    set_info_plist_value(bundle_path, "CFBundleIconFile", name.into())
+}
+
+struct SourceImage {
+    path: PathBuf,
+    // Original file content
+    data: Vec<u8>,
+    size: u32,
+    // Decoded RGBA pixels (premultiplied alpha)
+    pixels: Vec<u8>,
+}
+
+//
+// Creates .icns file with PNG encoded images for all sizes up to the largest source
+// image. Source image of exact size is embedded as is; Other sizes are scaled down
+// from closest larger source image.
+//
+pub fn create_icns(sources: &[PathBuf], out: &Path) -> ToolResult<()> {
+    let mut images = sources
+        .iter()
+        .map(|path| load_png(path))
+        .collect::<ToolResult<Vec<_>>>()?;
+    images.sort_by_key(|i| i.size);
+    let largest = images.last().map(|i| i.size).unwrap_or(0);
+
+    let mut elements = Vec::<(&[u8; 4], Vec<u8>)>::new();
+    // Retina variants share images with regular ones
+    let mut scaled = HashMap::<u32, Vec<u8>>::new();
+    for (icns_type, size) in ICNS_TYPES {
+        if *size > largest {
+            warn!(
+                "Skipping {}x{} icon; Largest source image is {}x{}",
+                size, size, largest, largest
+            );
+            continue;
+        }
+        let source = images.iter().find(|i| i.size >= *size).unwrap();
+        let data = if source.size == *size {
+            source.data.clone()
+        } else if let Some(data) = scaled.get(size) {
+            data.clone()
+        } else {
+            debug!("Scaling {:?} to {}x{}", source.path, size, size);
+            let data = encode_png(&resize(&source.pixels, source.size, *size), *size)?;
+            scaled.insert(*size, data.clone());
+            data
+        };
+        elements.push((icns_type, data));
+    }
+    if elements.is_empty() {
+        return Err(ToolError::OtherError(format!(
+            "Source images are too small for an icon; Largest is {}x{}, at least 16x16 is required",
+            largest, largest
+        )));
+    }
+
+    let length: usize = 8 + elements.iter().map(|(_, d)| d.len() + 8).sum::<usize>();
+    let mut icns = Vec::with_capacity(length);
+    icns.extend_from_slice(b"icns");
+    icns.extend_from_slice(&(length as u32).to_be_bytes());
+    for (icns_type, data) in elements {
+        icns.extend_from_slice(&icns_type[..]);
+        icns.extend_from_slice(&(data.len() as u32 + 8).to_be_bytes());
+        icns.extend_from_slice(&data);
+    }
+    debug!("Writing icon {:?}", out);
+    fs::write(out, icns).wrap_error(FileOperation::Write, || out.into())
+}
+
+fn load_png(path: &Path) -> ToolResult<SourceImage> {
+    let data = fs::read(path).wrap_error(FileOperation::Read, || path.into())?;
+    let invalid = |e: String| ToolError::OtherError(format!("Invalid PNG image {:?}: {}", path, e));
+    let mut decoder = png::Decoder::new(data.as_slice());
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|e| invalid(e.to_string()))?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let frame = reader
+        .next_frame(&mut buf)
+        .map_err(|e| invalid(e.to_string()))?;
+    if frame.width != frame.height {
+        return Err(invalid(format!(
+            "image must be square (is {}x{})",
+            frame.width, frame.height
+        )));
+    }
+    let channels = match frame.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+        png::ColorType::Indexed => return Err(invalid("unexpected indexed color".into())),
+    };
+    let mut pixels = Vec::with_capacity(frame.width as usize * frame.height as usize * 4);
+    for pixel in buf[..frame.buffer_size()].chunks(channels) {
+        let (rgb, alpha) = match channels {
+            1 => ([pixel[0]; 3], 255),
+            2 => ([pixel[0]; 3], pixel[1]),
+            3 => ([pixel[0], pixel[1], pixel[2]], 255),
+            _ => ([pixel[0], pixel[1], pixel[2]], pixel[3]),
+        };
+        for c in rgb {
+            pixels.push(((c as u32 * alpha as u32 + 127) / 255) as u8);
+        }
+        pixels.push(alpha);
+    }
+    Ok(SourceImage {
+        path: path.into(),
+        data,
+        size: frame.width,
+        pixels,
+    })
+}
+
+fn encode_png(pixels: &[u8], size: u32) -> ToolResult<Vec<u8>> {
+    // Un-premultiply alpha
+    let pixels: Vec<u8> = pixels
+        .chunks(4)
+        .flat_map(|p| {
+            let alpha = p[3] as u32;
+            let c = |c: u8| {
+                (c as u32 * 255 + alpha / 2)
+                    .checked_div(alpha)
+                    .map(|c| c.min(255) as u8)
+                    .unwrap_or(0)
+            };
+            vec![c(p[0]), c(p[1]), c(p[2]), p[3]]
+        })
+        .collect();
+    let mut res = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut res, size, size);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let error = |e: png::EncodingError| {
+            ToolError::OtherError(format!("Failed to encode PNG image: {}", e))
+        };
+        let mut writer = encoder.write_header().map_err(error)?;
+        writer.write_image_data(&pixels).map_err(error)?;
+        writer.finish().map_err(error)?;
+    }
+    Ok(res)
+}
+
+// Scales down square RGBA image (premultiplied alpha) using area averaging
+fn resize(pixels: &[u8], src: u32, dst: u32) -> Vec<u8> {
+    let (src, dst) = (src as usize, dst as usize);
+    let weights = area_weights(src, dst);
+    // Horizontal pass
+    let mut horizontal = vec![0f32; dst * src * 4];
+    for y in 0..src {
+        for (x, contributions) in weights.iter().enumerate() {
+            for (sx, weight) in contributions {
+                for c in 0..4 {
+                    horizontal[(y * dst + x) * 4 + c] +=
+                        pixels[(y * src + sx) * 4 + c] as f32 * weight;
+                }
+            }
+        }
+    }
+    // Vertical pass
+    let mut res = vec![0u8; dst * dst * 4];
+    for (y, contributions) in weights.iter().enumerate() {
+        for x in 0..dst {
+            for c in 0..4 {
+                let value: f32 = contributions
+                    .iter()
+                    .map(|(sy, weight)| horizontal[(sy * dst + x) * 4 + c] * weight)
+                    .sum();
+                res[(y * dst + x) * 4 + c] = value.round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+    res
+}
+
+// For each destination pixel returns source pixels and the portion of destination
+// pixel they cover.
+fn area_weights(src: usize, dst: usize) -> Vec<Vec<(usize, f32)>> {
+    let scale = src as f32 / dst as f32;
+    (0..dst)
+        .map(|i| {
+            let start = i as f32 * scale;
+            let end = start + scale;
+            let mut res = Vec::new();
+            let mut s = start.floor() as usize;
+            while (s as f32) < end && s < src {
+                let coverage = (end.min(s as f32 + 1.0) - start.max(s as f32)) / scale;
+                if coverage > 0.0 {
+                    res.push((s, coverage));
+                }
+                s += 1;
+            }
+            res
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::temp_dir;
+    use std::convert::TryInto;
+
+    // Writes opaque square PNG filled with given color
+    fn write_png(dir: &Path, size: u32, color: [u8; 3]) -> PathBuf {
+        let pixels: Vec<u8> = (0..size * size)
+            .flat_map(|_| vec![color[0], color[1], color[2], 255])
+            .collect();
+        let path = dir.join(format!("{}.png", size));
+        fs::write(&path, encode_png(&pixels, size).unwrap()).unwrap();
+        path
+    }
+
+    // (type, PNG payload) of all elements of .icns file
+    fn icns_elements(data: &[u8]) -> Vec<(String, Vec<u8>)> {
+        assert_eq!(&data[..4], b"icns");
+        assert_eq!(
+            u32::from_be_bytes(data[4..8].try_into().unwrap()) as usize,
+            data.len()
+        );
+        let mut res = Vec::new();
+        let mut pos = 8;
+        while pos < data.len() {
+            let length = u32::from_be_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+            let icns_type = String::from_utf8(data[pos..pos + 4].to_vec()).unwrap();
+            res.push((icns_type, data[pos + 8..pos + length].to_vec()));
+            pos += length;
+        }
+        assert_eq!(pos, data.len());
+        res
+    }
+
+    #[test]
+    fn computes_area_weights() {
+        assert_eq!(
+            area_weights(4, 2),
+            vec![vec![(0, 0.5), (1, 0.5)], vec![(2, 0.5), (3, 0.5)]]
+        );
+        let weights = area_weights(3, 2);
+        assert_eq!(weights.len(), 2);
+        assert_eq!(
+            weights[0].iter().map(|w| w.0).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert_eq!(
+            weights[1].iter().map(|w| w.0).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        for contributions in &weights {
+            let total: f32 = contributions.iter().map(|w| w.1).sum();
+            assert!((total - 1.0).abs() < 1e-6);
+        }
+        assert_eq!(area_weights(2, 2), vec![vec![(0, 1.0)], vec![(1, 1.0)]]);
+    }
+
+    #[test]
+    fn resizes_by_averaging() {
+        // 2x2 image of black and white pixels averages to grey
+        let pixels = [
+            0, 0, 0, 255, 255, 255, 255, 255, //
+            255, 255, 255, 255, 0, 0, 0, 255,
+        ];
+        assert_eq!(resize(&pixels, 2, 1), vec![128, 128, 128, 255]);
+        let uniform: Vec<u8> = [10u8, 20, 30, 255].repeat(9);
+        assert_eq!(resize(&uniform, 3, 2), [10u8, 20, 30, 255].repeat(4));
+    }
+
+    #[test]
+    fn creates_icns() {
+        let dir = temp_dir().unwrap();
+        let source = write_png(&dir, 32, [255, 0, 0]);
+        let out = dir.join("icon.icns");
+        create_icns(std::slice::from_ref(&source), &out).unwrap();
+        let elements = icns_elements(&fs::read(&out).unwrap());
+        let types: Vec<_> = elements.iter().map(|(t, _)| t.as_str()).collect();
+        assert_eq!(types, vec!["icp4", "ic11", "icp5"]);
+        // Source of exact size is embedded as is, smaller size is scaled down
+        let original = fs::read(&source).unwrap();
+        assert_eq!(elements[1].1, original);
+        assert_eq!(elements[2].1, original);
+        let decoder = png::Decoder::new(elements[0].1.as_slice());
+        let reader = decoder.read_info().unwrap();
+        assert_eq!(reader.info().width, 16);
+        assert_eq!(reader.info().height, 16);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_small_sources() {
+        let dir = temp_dir().unwrap();
+        let source = write_png(&dir, 8, [0, 0, 255]);
+        let out = dir.join("icon.icns");
+        assert!(create_icns(&[source], &out).is_err());
+        assert!(!out.exists());
+        assert!(create_icns(&[], &out).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,428 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, warn};
+
+use crate::{
+    cargo::package_version,
+    config::{apply, apply_flag, required, BundleConfig},
+    error::{FileOperation, IOResultExt, PlistResultExt, ToolError, ToolResult},
+};
+
+#[derive(clap::Parser)]
+pub struct InfoPlistOptions {
+    /// Bundle identifier (CFBundleIdentifier)
+    #[clap(long)]
+    bundle_identifier: Option<String>,
+
+    /// Version (CFBundleShortVersionString and CFBundleVersion). Defaults to package
+    /// version from Cargo.toml
+    #[clap(long)]
+    bundle_version: Option<String>,
+
+    /// Keep version in Info.plist instead of using package version from Cargo.toml
+    #[clap(long, overrides_with = "cargo_version")]
+    no_cargo_version: bool,
+
+    /// Use package version from Cargo.toml (overrides configuration)
+    #[clap(long, overrides_with = "no_cargo_version")]
+    cargo_version: bool,
+
+    /// Minimum macOS version (LSMinimumSystemVersion)
+    #[clap(long)]
+    minimum_system_version: Option<String>,
+
+    /// URL scheme handled by the app (added to CFBundleURLTypes). Can be specified
+    /// multiple times.
+    #[clap(long)]
+    url_scheme: Vec<String>,
+
+    /// Plist file with keys to merge into Info.plist (i.e. CFBundleDocumentTypes).
+    /// Dictionaries are merged recursively, other values are replaced. Can be specified
+    /// multiple times.
+    #[clap(long)]
+    plist_fragment: Vec<PathBuf>,
+
+    /// Info.plist key to set in form of <key>=<value>. Value is parsed as JSON if it is
+    /// true, false or starts with [ or {; Otherwise it is a string. Can be specified
+    /// multiple times.
+    #[clap(long)]
+    plist_set: Vec<String>,
+
+    // Keys from [bundle.info-plist] configuration table
+    #[clap(skip)]
+    config_keys: plist::Dictionary,
+}
+
+impl InfoPlistOptions {
+    pub fn apply_config(&mut self, config: &BundleConfig) {
+        apply(&mut self.bundle_identifier, &config.bundle_identifier);
+        apply(&mut self.bundle_version, &config.bundle_version);
+        apply(
+            &mut self.minimum_system_version,
+            &config.minimum_system_version,
+        );
+        apply_flag(
+            &mut self.no_cargo_version,
+            self.cargo_version,
+            config.cargo_version.map(|v| !v),
+        );
+        if self.url_scheme.is_empty() {
+            self.url_scheme = config.url_schemes.clone();
+        }
+        if self.plist_fragment.is_empty() {
+            self.plist_fragment = config.plist_fragments.clone();
+        }
+        self.config_keys = config
+            .info_plist
+            .iter()
+            .map(|(key, value)| (key.clone(), toml_to_plist(value)))
+            .collect();
+    }
+
+    pub fn bundle_identifier(&self) -> Option<&str> {
+        self.bundle_identifier.as_deref()
+    }
+}
+
+#[derive(clap::Parser)]
+pub struct Options {
+    /// Path to bundle which Info.plist should be updated
+    bundle_path: Option<PathBuf>,
+
+    #[clap(flatten)]
+    info_plist: InfoPlistOptions,
+}
+
+impl Options {
+    pub fn apply_config(&mut self, config: &BundleConfig) {
+        apply(&mut self.bundle_path, &config.bundle_path);
+        self.info_plist.apply_config(config);
+    }
+}
+
+pub struct InfoPlist {
+    options: Options,
+}
+
+impl InfoPlist {
+    pub fn new(options: Options) -> Self {
+        Self { options }
+    }
+
+    pub fn perform(self) -> ToolResult<()> {
+        let bundle_path = required(&self.options.bundle_path, "bundle path")?;
+        update_info_plist(&bundle_path, &self.options.info_plist)
+    }
+}
+
+//
+// Updates Contents/Info.plist of given bundle. Keys are applied in order (later
+// override earlier):
+//   - [bundle.info-plist] configuration table
+//   - plist fragments
+//   - bundle identifier, version (from Cargo.toml unless specified), minimum system
+//     version and URL schemes
+//   - --plist-set arguments
+//
+pub fn update_info_plist(bundle_path: &Path, options: &InfoPlistOptions) -> ToolResult<()> {
+    let info_plist = bundle_path.join("Contents").join("Info.plist");
+    let mut info = read_info_plist(&info_plist)?;
+
+    merge(&mut info, options.config_keys.clone());
+
+    for fragment in &options.plist_fragment {
+        debug!("Merging plist fragment {:?}", fragment);
+        let fragment_keys = plist::Value::from_file(fragment)
+            .wrap_error(|| Some(fragment.clone()))?
+            .into_dictionary()
+            .ok_or_else(|| {
+                ToolError::OtherError(format!(
+                    "Plist fragment {:?} does not contain a dictionary",
+                    fragment
+                ))
+            })?;
+        merge(&mut info, fragment_keys);
+    }
+
+    if let Some(identifier) = &options.bundle_identifier {
+        info.insert("CFBundleIdentifier".into(), identifier.clone().into());
+    }
+
+    let version = match &options.bundle_version {
+        Some(version) => Some(version.clone()),
+        None if !options.no_cargo_version => cargo_bundle_version()?,
+        None => None,
+    };
+    if let Some(version) = version {
+        info.insert("CFBundleShortVersionString".into(), version.clone().into());
+        info.insert("CFBundleVersion".into(), version.into());
+    }
+
+    if let Some(minimum_system_version) = &options.minimum_system_version {
+        info.insert(
+            "LSMinimumSystemVersion".into(),
+            minimum_system_version.clone().into(),
+        );
+    }
+
+    if !options.url_scheme.is_empty() {
+        add_url_schemes(&mut info, &options.url_scheme).ok_or_else(|| {
+            ToolError::OtherError(format!(
+                "CFBundleURLTypes in {:?} is not an array of dictionaries",
+                info_plist
+            ))
+        })?;
+    }
+
+    for entry in &options.plist_set {
+        let (key, value) = parse_plist_set(entry)?;
+        info.insert(key, value);
+    }
+
+    write_info_plist(&info_plist, info)
+}
+
+// Bundle version from package version in Cargo.toml of current directory, if any
+pub fn cargo_bundle_version() -> ToolResult<Option<String>> {
+    Ok(package_version()?.map(|v| bundle_version(&v)))
+}
+
+// Bundle versions may only contain numbers and periods, so pre-release and build
+// metadata of package version (i.e. "1.0.0-beta.1+abc") are dropped.
+fn bundle_version(package_version: &str) -> String {
+    let version = package_version.split(['-', '+']).next().unwrap_or_default();
+    if version != package_version {
+        warn!(
+            "Using version {} for package version {}; Pre-release and build metadata are not allowed in bundle version",
+            version, package_version
+        );
+    }
+    version.into()
+}
+
+//
+// Adds URL schemes that are not declared yet, so that repeated runs don't duplicate
+// entries. Schemes are added to URL type named after bundle identifier if there is
+// one, otherwise new URL type is created. Returns None if CFBundleURLTypes is
+// malformed.
+//
+fn add_url_schemes(info: &mut plist::Dictionary, schemes: &[String]) -> Option<()> {
+    let identifier = info.get("CFBundleIdentifier").cloned();
+    if !info.contains_key("CFBundleURLTypes") {
+        info.insert("CFBundleURLTypes".into(), plist::Value::Array(Vec::new()));
+    }
+    let url_types = info.get_mut("CFBundleURLTypes")?.as_array_mut()?;
+    let mut existing = Vec::new();
+    for url_type in url_types.iter() {
+        let declared = url_type.as_dictionary()?.get("CFBundleURLSchemes");
+        existing.extend(declared.and_then(|s| s.as_array()).into_iter().flatten());
+    }
+    let mut new_schemes: Vec<plist::Value> = Vec::new();
+    for scheme in schemes {
+        let scheme = plist::Value::from(scheme.clone());
+        if !existing.contains(&&scheme) && !new_schemes.contains(&scheme) {
+            new_schemes.push(scheme);
+        }
+    }
+    if new_schemes.is_empty() {
+        return Some(());
+    }
+    let named = url_types.iter_mut().find(|u| {
+        identifier.is_some()
+            && u.as_dictionary().and_then(|u| u.get("CFBundleURLName")) == identifier.as_ref()
+    });
+    match named {
+        Some(url_type) => {
+            let url_type = url_type.as_dictionary_mut()?;
+            if !url_type.contains_key("CFBundleURLSchemes") {
+                url_type.insert("CFBundleURLSchemes".into(), plist::Value::Array(Vec::new()));
+            }
+            url_type
+                .get_mut("CFBundleURLSchemes")?
+                .as_array_mut()?
+                .extend(new_schemes);
+        }
+        None => {
+            let mut url_type = plist::Dictionary::new();
+            if let Some(identifier) = identifier {
+                url_type.insert("CFBundleURLName".into(), identifier);
+            }
+            url_type.insert(
+                "CFBundleURLSchemes".into(),
+                plist::Value::Array(new_schemes),
+            );
+            url_types.push(url_type.into());
+        }
+    }
+    Some(())
+}
+
+// Sets single key in Contents/Info.plist of given bundle
+pub fn set_info_plist_value(bundle_path: &Path, key: &str, value: plist::Value) -> ToolResult<()> {
+    let info_plist = bundle_path.join("Contents").join("Info.plist");
+    let mut info = read_info_plist(&info_plist)?;
+    info.insert(key.into(), value);
+    write_info_plist(&info_plist, info)
+}
+
+fn read_info_plist(info_plist: &Path) -> ToolResult<plist::Dictionary> {
+    plist::Value::from_file(info_plist)
+        .wrap_error(|| Some(info_plist.into()))?
+        .into_dictionary()
+        .ok_or_else(|| {
+            ToolError::OtherError(format!("{:?} does not contain a dictionary", info_plist))
+        })
+}
+
+// Written to temporary file first and renamed over the original, so that failed
+// write doesn't leave truncated Info.plist behind and files hardlinked to it are not
+// modified.
+fn write_info_plist(info_plist: &Path, info: plist::Dictionary) -> ToolResult<()> {
+    debug!("Updating {:?}", info_plist);
+    let temp_path = info_plist.with_extension("plist.tmp");
+    plist::Value::Dictionary(info)
+        .to_file_xml(&temp_path)
+        .wrap_error(|| Some(temp_path.clone()))?;
+    fs::rename(&temp_path, info_plist).wrap_error(FileOperation::Move, || info_plist.into())
+}
+
+// Merges keys into dictionary; Nested dictionaries are merged recursively.
+fn merge(target: &mut plist::Dictionary, keys: plist::Dictionary) {
+    for (key, value) in keys {
+        match (target.get_mut(&key), value) {
+            (Some(plist::Value::Dictionary(existing)), plist::Value::Dictionary(value)) => {
+                merge(existing, value)
+            }
+            (_, value) => {
+                target.insert(key, value);
+            }
+        }
+    }
+}
+
+fn parse_plist_set(entry: &str) -> ToolResult<(String, plist::Value)> {
+    let (key, value) = entry.split_once('=').ok_or_else(|| {
+        ToolError::OtherError(format!(
+            "Invalid plist value \"{}\"; Expected <key>=<value>",
+            entry
+        ))
+    })?;
+    let is_json = value == "true" || value == "false" || value.starts_with(['[', '{']);
+    let value = if is_json {
+        let value: serde_json::Value = serde_json::from_str(value).map_err(|e| {
+            ToolError::OtherError(format!("Invalid plist value for {}: {}", key, e))
+        })?;
+        json_to_plist(&value)
+    } else {
+        value.into()
+    };
+    Ok((key.into(), value))
+}
+
+fn json_to_plist(value: &serde_json::Value) -> plist::Value {
+    match value {
+        serde_json::Value::Null => plist::Value::String(String::new()),
+        serde_json::Value::Bool(b) => (*b).into(),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => i.into(),
+            None => n.as_f64().unwrap_or_default().into(),
+        },
+        serde_json::Value::String(s) => s.clone().into(),
+        serde_json::Value::Array(a) => plist::Value::Array(a.iter().map(json_to_plist).collect()),
+        serde_json::Value::Object(o) => plist::Value::Dictionary(
+            o.iter()
+                .map(|(k, v)| (k.clone(), json_to_plist(v)))
+                .collect(),
+        ),
+    }
+}
+
+fn toml_to_plist(value: &toml::Value) -> plist::Value {
+    match value {
+        toml::Value::String(s) => s.clone().into(),
+        toml::Value::Integer(i) => (*i).into(),
+        toml::Value::Float(f) => (*f).into(),
+        toml::Value::Boolean(b) => (*b).into(),
+        toml::Value::Datetime(d) => d.to_string().into(),
+        toml::Value::Array(a) => plist::Value::Array(a.iter().map(toml_to_plist).collect()),
+        toml::Value::Table(t) => plist::Value::Dictionary(
+            t.iter()
+                .map(|(k, v)| (k.clone(), toml_to_plist(v)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url_types(info: &plist::Dictionary) -> &Vec<plist::Value> {
+        info.get("CFBundleURLTypes").unwrap().as_array().unwrap()
+    }
+
+    fn schemes(url_type: &plist::Value) -> Vec<&str> {
+        url_type.as_dictionary().unwrap()["CFBundleURLSchemes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s.as_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn strips_version_metadata() {
+        assert_eq!(bundle_version("1.2.3"), "1.2.3");
+        assert_eq!(bundle_version("0.3.0-beta.1"), "0.3.0");
+        assert_eq!(bundle_version("1.0.0+abc"), "1.0.0");
+        assert_eq!(bundle_version("1.0.0-rc.1+build-5"), "1.0.0");
+    }
+
+    #[test]
+    fn adds_url_schemes_once() {
+        let mut info = plist::Dictionary::new();
+        info.insert("CFBundleIdentifier".into(), "com.example.app".into());
+        let args = vec!["foo".to_string(), "bar".into(), "foo".into()];
+        add_url_schemes(&mut info, &args).unwrap();
+        add_url_schemes(&mut info, &args).unwrap();
+        let types = url_types(&info);
+        assert_eq!(types.len(), 1);
+        assert_eq!(
+            types[0].as_dictionary().unwrap()["CFBundleURLName"].as_string(),
+            Some("com.example.app")
+        );
+        assert_eq!(schemes(&types[0]), vec!["foo", "bar"]);
+
+        add_url_schemes(&mut info, &["baz".to_string()]).unwrap();
+        let types = url_types(&info);
+        assert_eq!(types.len(), 1);
+        assert_eq!(schemes(&types[0]), vec!["foo", "bar", "baz"]);
+    }
+
+    #[test]
+    fn keeps_other_url_types() {
+        let mut other = plist::Dictionary::new();
+        other.insert("CFBundleURLName".into(), "com.example.other".into());
+        other.insert(
+            "CFBundleURLSchemes".into(),
+            plist::Value::Array(vec!["other".into()]),
+        );
+        let mut info = plist::Dictionary::new();
+        info.insert("CFBundleIdentifier".into(), "com.example.app".into());
+        info.insert(
+            "CFBundleURLTypes".into(),
+            plist::Value::Array(vec![other.into()]),
+        );
+        add_url_schemes(&mut info, &["other".to_string(), "app".into()]).unwrap();
+        let types = url_types(&info);
+        assert_eq!(types.len(), 2);
+        assert_eq!(schemes(&types[0]), vec!["other"]);
+        assert_eq!(schemes(&types[1]), vec!["app"]);
+
+        info.insert("CFBundleURLTypes".into(), "invalid".into());
+        assert!(add_url_schemes(&mut info, &["app".to_string()]).is_none());
+    }
+}
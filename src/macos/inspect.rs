@@ -0,0 +1,336 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use serde::Serialize;
+
+use crate::{
+    config::required,
+    error::{FileOperation, IOResultExt, ToolError, ToolResult},
+};
+
+use super::{
+    macho::MachFile,
+    utils::{classify_binary, format_size, write_stdout, BinaryType},
+};
+
+// Info.plist keys shown in plist summary
+const SUMMARY_KEYS: &[&str] = &[
+    "CFBundleIdentifier",
+    "CFBundleExecutable",
+    "CFBundlePackageType",
+    "CFBundleShortVersionString",
+    "CFBundleVersion",
+    "LSMinimumSystemVersion",
+];
+
+#[derive(clap::Parser)]
+pub struct Options {
+    /// Path to bundle to inspect
+    bundle_path: Option<PathBuf>,
+
+    /// Print bundle content as JSON
+    #[clap(long)]
+    json: bool,
+}
+
+pub struct Inspect {
+    options: Options,
+}
+
+#[derive(Serialize)]
+struct Node {
+    name: String,
+    size: u64,
+    #[serde(flatten)]
+    kind: NodeKind,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum NodeKind {
+    Directory {
+        children: Vec<Node>,
+    },
+    File {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        macho: Option<MachInfo>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        plist: Option<PlistSummary>,
+    },
+    Symlink {
+        target: PathBuf,
+    },
+}
+
+#[derive(Serialize)]
+pub(super) struct MachInfo {
+    #[serde(rename = "type")]
+    pub binary_type: String,
+    pub archs: Vec<String>,
+    pub signed: bool,
+    pub install_name: Option<String>,
+    pub rpaths: Vec<String>,
+    pub dependencies: Vec<Dependency>,
+}
+
+#[derive(Serialize)]
+pub(super) struct Dependency {
+    pub name: String,
+    pub weak: bool,
+}
+
+#[derive(Serialize)]
+struct PlistSummary {
+    keys: usize,
+    // Well-known string values
+    values: BTreeMap<String, String>,
+}
+
+impl Inspect {
+    pub fn new(options: Options) -> Self {
+        Self { options }
+    }
+
+    pub fn perform(self) -> ToolResult<()> {
+        let bundle_path = required(&self.options.bundle_path, "bundle path")?;
+        if !bundle_path.is_dir() {
+            return Err(ToolError::OtherError(format!(
+                "Bundle {:?} does not exist",
+                bundle_path
+            )));
+        }
+        let (root, _) = inspect_path(&bundle_path, &mut HashSet::new())?;
+        let out = if self.options.json {
+            let mut json = serde_json::to_string_pretty(&root).map_err(|e| {
+                ToolError::OtherError(format!("Failed to serialize bundle content: {}", e))
+            })?;
+            json.push('\n');
+            json
+        } else {
+            let mut out = String::new();
+            print_node(&root, 0, &mut out);
+            out
+        };
+        write_stdout(&out)
+    }
+}
+
+// Builds tree for given path; Directory size is the sum of its content, with
+// hardlinked files counted once (where first seen), same as in macos-size. Returns the
+// node along with number of bytes it adds to the total.
+fn inspect_path(path: &Path, seen: &mut HashSet<(u64, u64)>) -> ToolResult<(Node, u64)> {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into())
+        .unwrap_or_else(|| path.to_string_lossy().into());
+    let meta = path
+        .symlink_metadata()
+        .wrap_error(FileOperation::Metadata, || path.into())?;
+    if meta.file_type().is_symlink() {
+        let target = fs::read_link(path).wrap_error(FileOperation::ReadLink, || path.into())?;
+        Ok((
+            Node {
+                name,
+                size: 0,
+                kind: NodeKind::Symlink { target },
+            },
+            0,
+        ))
+    } else if meta.is_dir() {
+        let mut entries = Vec::new();
+        for entry in path
+            .read_dir()
+            .wrap_error(FileOperation::ReadDir, || path.into())?
+        {
+            let entry = entry.wrap_error(FileOperation::Read, || path.into())?;
+            entries.push(entry.path());
+        }
+        entries.sort();
+        let mut children = Vec::new();
+        let mut size = 0;
+        for entry in entries {
+            let (child, child_size) = inspect_path(&entry, seen)?;
+            children.push(child);
+            size += child_size;
+        }
+        Ok((
+            Node {
+                name,
+                size,
+                kind: NodeKind::Directory { children },
+            },
+            size,
+        ))
+    } else {
+        let plist = if path.extension().map(|e| e == "plist").unwrap_or(false) {
+            summarize_plist(path)
+        } else {
+            None
+        };
+        let counted = if seen.insert((meta.dev(), meta.ino())) {
+            meta.len()
+        } else {
+            0
+        };
+        Ok((
+            Node {
+                name,
+                size: meta.len(),
+                kind: NodeKind::File {
+                    macho: inspect_binary(path)?,
+                    plist,
+                },
+            },
+            counted,
+        ))
+    }
+}
+
+pub(super) fn inspect_binary(path: &Path) -> ToolResult<Option<MachInfo>> {
+    let info = match classify_binary(path)? {
+        Some(info) => info,
+        None => return Ok(None),
+    };
+    let mut res = MachInfo {
+        binary_type: binary_type_name(info.binary_type),
+        archs: info.archs,
+        signed: info.signed,
+        install_name: None,
+        rpaths: Vec::new(),
+        dependencies: Vec::new(),
+    };
+    if info.binary_type == BinaryType::Archive {
+        return Ok(Some(res));
+    }
+    let file = match MachFile::read(path)? {
+        Some(file) => file,
+        None => return Ok(Some(res)),
+    };
+    // Load commands of all slices; These usually match, so entries are deduplicated
+    for slice in file.slices() {
+        if res.install_name.is_none() {
+            res.install_name = slice.install_name();
+        }
+        for rpath in slice.rpaths() {
+            if !res.rpaths.contains(&rpath) {
+                res.rpaths.push(rpath);
+            }
+        }
+        for reference in slice.dylib_references() {
+            if !res.dependencies.iter().any(|d| d.name == reference.name) {
+                res.dependencies.push(Dependency {
+                    weak: reference.is_weak(),
+                    name: reference.name,
+                });
+            }
+        }
+    }
+    Ok(Some(res))
+}
+
+fn binary_type_name(binary_type: BinaryType) -> String {
+    match binary_type {
+        BinaryType::Executable => "executable".into(),
+        BinaryType::Dylib => "dylib".into(),
+        BinaryType::Bundle => "bundle".into(),
+        BinaryType::KextBundle => "kext".into(),
+        BinaryType::Dsym => "dsym".into(),
+        BinaryType::Object => "object".into(),
+        BinaryType::Archive => "archive".into(),
+        BinaryType::Other(file_type) => format!("filetype {:#x}", file_type),
+    }
+}
+
+// Unreadable plists are not an error; They are shown as regular files.
+fn summarize_plist(path: &Path) -> Option<PlistSummary> {
+    let value = match plist::Value::from_file(path) {
+        Ok(value) => value,
+        Err(err) => {
+            debug!("{:?}: failed to read plist ({})", path, err);
+            return None;
+        }
+    };
+    let dictionary = value.as_dictionary()?;
+    Some(PlistSummary {
+        keys: dictionary.len(),
+        values: SUMMARY_KEYS
+            .iter()
+            .filter_map(|key| {
+                let value = dictionary.get(key)?.as_string()?;
+                Some((key.to_string(), value.into()))
+            })
+            .collect(),
+    })
+}
+
+fn print_node(node: &Node, depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth);
+    match &node.kind {
+        NodeKind::Directory { children } => {
+            out.push_str(&format!(
+                "{}{}/ ({})\n",
+                indent,
+                node.name,
+                format_size(node.size)
+            ));
+            for child in children {
+                print_node(child, depth + 1, out);
+            }
+        }
+        NodeKind::Symlink { target } => {
+            out.push_str(&format!(
+                "{}{} -> {}\n",
+                indent,
+                node.name,
+                target.display()
+            ));
+        }
+        NodeKind::File { macho, plist } => {
+            out.push_str(&format!(
+                "{}{} ({})",
+                indent,
+                node.name,
+                format_size(node.size)
+            ));
+            if let Some(macho) = macho {
+                out.push_str(&format!(
+                    " Mach-O {} [{}]{}",
+                    macho.binary_type,
+                    macho.archs.join(", "),
+                    if macho.signed { " signed" } else { " unsigned" }
+                ));
+            }
+            if let Some(plist) = plist {
+                out.push_str(&format!(" plist, {} keys", plist.keys));
+            }
+            out.push('\n');
+            let detail = "  ".repeat(depth + 1);
+            if let Some(macho) = macho {
+                if let Some(install_name) = &macho.install_name {
+                    out.push_str(&format!("{}install name: {}\n", detail, install_name));
+                }
+                for rpath in &macho.rpaths {
+                    out.push_str(&format!("{}rpath: {}\n", detail, rpath));
+                }
+                for dependency in &macho.dependencies {
+                    out.push_str(&format!(
+                        "{}depends on: {}{}\n",
+                        detail,
+                        dependency.name,
+                        if dependency.weak { " (weak)" } else { "" }
+                    ));
+                }
+            }
+            if let Some(plist) = plist {
+                for (key, value) in &plist.values {
+                    out.push_str(&format!("{}{}: {}\n", detail, key, value));
+                }
+            }
+        }
+    }
+}
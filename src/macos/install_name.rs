@@ -0,0 +1,132 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use log::debug;
+
+use crate::{
+    error::{HeaderPaddingIssue, ToolError, ToolResult},
+    utils::run_command,
+};
+
+use super::macho::{
+    MachFile, LC_ID_DYLIB, LC_LAZY_LOAD_DYLIB, LC_LOAD_DYLIB, LC_LOAD_UPWARD_DYLIB,
+    LC_LOAD_WEAK_DYLIB, LC_REEXPORT_DYLIB,
+};
+
+// Size of dylib_command and rpath_command without the string payload
+const DYLIB_COMMAND_SIZE: usize = 24;
+const RPATH_COMMAND_SIZE: usize = 12;
+
+// Load command changes planned for single module
+#[derive(Default, Debug)]
+pub struct ModuleChanges {
+    pub changes: Vec<(String, String)>,
+    pub id: Option<String>,
+    pub rpaths: Vec<String>,
+}
+
+impl ModuleChanges {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.id.is_none() && self.rpaths.is_empty()
+    }
+
+    fn apply(&self, path: &Path) -> ToolResult<()> {
+        debug!("Changing load commands for {:?}: {:?}", path, self);
+        let mut cmd = Command::new("install_name_tool");
+        for (from, to) in &self.changes {
+            cmd.arg("-change").arg(from).arg(to);
+        }
+        if let Some(id) = &self.id {
+            cmd.arg("-id").arg(id);
+        }
+        for rpath in &self.rpaths {
+            cmd.arg("-add_rpath").arg(rpath);
+        }
+        cmd.arg(path);
+        run_command(cmd, "install_name_tool")?;
+        Ok(())
+    }
+
+    // Returns (arch, missing bytes) for each slice where the changes don't fit into
+    // header padding.
+    fn missing_padding(&self, path: &Path) -> ToolResult<Vec<(String, u64)>> {
+        let file = match MachFile::read(path)? {
+            Some(file) => file,
+            None => return Ok(Vec::new()),
+        };
+        let mut res = Vec::new();
+        for slice in file.slices() {
+            let mut required: i64 = 0;
+            for command in slice.load_commands() {
+                let new_name = match command.cmd {
+                    LC_ID_DYLIB => self.id.clone(),
+                    LC_LOAD_DYLIB | LC_LOAD_WEAK_DYLIB | LC_REEXPORT_DYLIB | LC_LAZY_LOAD_DYLIB
+                    | LC_LOAD_UPWARD_DYLIB => {
+                        let name = command.string_at(8).unwrap_or_default();
+                        self.changes
+                            .iter()
+                            .find(|(from, _)| *from == name)
+                            .map(|(_, to)| to.clone())
+                    }
+                    _ => None,
+                };
+                if let Some(new_name) = new_name {
+                    let new_size = slice.string_command_size(DYLIB_COMMAND_SIZE, &new_name);
+                    required += new_size as i64 - command.data.len() as i64;
+                }
+            }
+            for rpath in &self.rpaths {
+                required += slice.string_command_size(RPATH_COMMAND_SIZE, rpath) as i64;
+            }
+            let available = slice.header_padding() as i64;
+            if required > available {
+                res.push((slice.arch_name(), (required - available) as u64));
+            }
+        }
+        Ok(res)
+    }
+}
+
+// Collects load command changes for modules in bundle so that they can be verified
+// before any binary is modified.
+#[derive(Default)]
+pub struct PlannedChanges {
+    modules: BTreeMap<PathBuf, ModuleChanges>,
+}
+
+impl PlannedChanges {
+    pub fn module(&mut self, path: &Path) -> &mut ModuleChanges {
+        self.modules.entry(path.into()).or_default()
+    }
+
+    // Verifies that all planned changes fit within header padding of respective binaries
+    pub fn check_header_padding(&self, bundle_path: &Path) -> ToolResult<()> {
+        let mut issues = Vec::new();
+        for (path, changes) in &self.modules {
+            for (arch, missing_bytes) in changes.missing_padding(path)? {
+                issues.push(HeaderPaddingIssue {
+                    path: pathdiff::diff_paths(path, bundle_path).unwrap(),
+                    arch,
+                    missing_bytes,
+                });
+            }
+        }
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(ToolError::HeaderPadding(issues))
+        }
+    }
+
+    pub fn apply(&self) -> ToolResult<()> {
+        for (path, changes) in &self.modules {
+            if !changes.is_empty() {
+                changes.apply(path)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,452 @@
+use std::path::{Path, PathBuf};
+
+use log::{debug, info, warn};
+
+use crate::{
+    config::{apply, apply_flag, required, LintConfig},
+    error::{FileOperation, IOResultExt, LintFinding, Severity, ToolError, ToolResult},
+};
+
+use super::{
+    macho::Version,
+    utils::{classify_binary, BinaryType},
+};
+
+// Keys that must be present in Info.plist of an app bundle
+const REQUIRED_KEYS: &[(&str, Severity)] = &[
+    ("CFBundleExecutable", Severity::Error),
+    ("CFBundleIdentifier", Severity::Error),
+    ("CFBundlePackageType", Severity::Warning),
+    ("CFBundleName", Severity::Warning),
+    ("CFBundleInfoDictionaryVersion", Severity::Warning),
+    ("CFBundleShortVersionString", Severity::Warning),
+    ("CFBundleVersion", Severity::Warning),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum ValueType {
+    String,
+    Boolean,
+    Array,
+    Dictionary,
+}
+
+// Expected types of well-known keys
+const KEY_TYPES: &[(&str, ValueType)] = &[
+    ("CFBundleExecutable", ValueType::String),
+    ("CFBundleIdentifier", ValueType::String),
+    ("CFBundlePackageType", ValueType::String),
+    ("CFBundleName", ValueType::String),
+    ("CFBundleDisplayName", ValueType::String),
+    ("CFBundleInfoDictionaryVersion", ValueType::String),
+    ("CFBundleShortVersionString", ValueType::String),
+    ("CFBundleVersion", ValueType::String),
+    ("CFBundleDevelopmentRegion", ValueType::String),
+    ("CFBundleIconFile", ValueType::String),
+    ("CFBundleIconName", ValueType::String),
+    ("CFBundleURLTypes", ValueType::Array),
+    ("CFBundleDocumentTypes", ValueType::Array),
+    ("LSMinimumSystemVersion", ValueType::String),
+    ("LSApplicationCategoryType", ValueType::String),
+    ("LSUIElement", ValueType::Boolean),
+    ("LSBackgroundOnly", ValueType::Boolean),
+    ("NSHighResolutionCapable", ValueType::Boolean),
+    ("NSPrincipalClass", ValueType::String),
+    ("NSHumanReadableCopyright", ValueType::String),
+    ("NSAppTransportSecurity", ValueType::Dictionary),
+];
+
+// Entries allowed directly in Contents folder besides folders
+const CONTENTS_FILES: &[&str] = &["Info.plist", "PkgInfo", "embedded.provisionprofile"];
+
+// Folders that may contain nested bundles
+const NESTED_BUNDLE_DIRS: &[&str] = &["Library/LoginItems", "XPCServices", "Helpers"];
+
+#[derive(clap::Parser)]
+pub struct Options {
+    /// Path to bundle to lint
+    bundle_path: Option<PathBuf>,
+
+    /// Treat warnings as errors
+    #[clap(long, overrides_with = "no_strict")]
+    strict: bool,
+
+    /// Report warnings without failing (overrides configuration)
+    #[clap(long, overrides_with = "strict")]
+    no_strict: bool,
+}
+
+impl Options {
+    pub fn apply_config(&mut self, config: &LintConfig) {
+        apply(&mut self.bundle_path, &config.bundle_path);
+        apply_flag(&mut self.strict, self.no_strict, config.strict);
+    }
+}
+
+pub struct Lint {
+    options: Options,
+    bundle_path: PathBuf,
+    findings: Vec<LintFinding>,
+}
+
+impl Lint {
+    pub fn new(options: Options) -> Self {
+        Self {
+            options,
+            bundle_path: PathBuf::new(),
+            findings: Vec::new(),
+        }
+    }
+
+    //
+    // Checks Info.plist and structure of bundle (and nested app and XPC bundles):
+    //   - required keys are present and well-known keys have correct type
+    //   - CFBundleExecutable exists in Contents/MacOS and is a Mach-O executable
+    //   - icon file exists
+    //   - bundle identifier is a valid reverse-DNS string
+    //   - version strings are well-formed
+    //   - there are no stray files at the bundle root or in Contents
+    //
+    pub fn perform(mut self) -> ToolResult<()> {
+        self.bundle_path = required(&self.options.bundle_path, "bundle path")?;
+        if !self.bundle_path.is_dir() {
+            return Err(ToolError::OtherError(format!(
+                "Bundle {:?} does not exist",
+                self.bundle_path
+            )));
+        }
+        self.lint_bundle(&self.bundle_path.clone())?;
+
+        let strict = self.options.strict;
+        let failed = self
+            .findings
+            .iter()
+            .any(|f| f.severity == Severity::Error || strict);
+        if failed {
+            return Err(ToolError::LintFindings(self.findings));
+        }
+        for finding in &self.findings {
+            warn!("{}", finding);
+        }
+        info!("Bundle lint passed");
+        Ok(())
+    }
+
+    // Path is reported relative to bundle; Findings for the bundle itself show ".".
+    fn report(&mut self, path: &Path, severity: Severity, message: String) {
+        let path = match pathdiff::diff_paths(path, &self.bundle_path) {
+            Some(relative) if relative.as_os_str().is_empty() => ".".into(),
+            Some(relative) => relative,
+            None => path.into(),
+        };
+        self.findings.push(LintFinding {
+            path,
+            severity,
+            message,
+        });
+    }
+
+    fn lint_bundle(&mut self, bundle_path: &Path) -> ToolResult<()> {
+        debug!("Linting {:?}", bundle_path);
+        let contents = bundle_path.join("Contents");
+        if !contents.is_dir() {
+            self.report(
+                bundle_path,
+                Severity::Error,
+                "Bundle has no Contents folder".into(),
+            );
+            return Ok(());
+        }
+        self.lint_structure(bundle_path)?;
+
+        let info_plist = contents.join("Info.plist");
+        if !info_plist.is_file() {
+            self.report(&info_plist, Severity::Error, "Info.plist is missing".into());
+            return Ok(());
+        }
+        let info = match plist::Value::from_file(&info_plist) {
+            Ok(plist::Value::Dictionary(info)) => info,
+            Ok(_) => {
+                self.report(
+                    &info_plist,
+                    Severity::Error,
+                    "Info.plist does not contain a dictionary".into(),
+                );
+                return Ok(());
+            }
+            Err(e) => {
+                self.report(
+                    &info_plist,
+                    Severity::Error,
+                    format!("Info.plist can not be parsed: {}", e),
+                );
+                return Ok(());
+            }
+        };
+        self.lint_keys(&info_plist, &info);
+
+        let string = |key: &str| info.get(key).and_then(|v| v.as_string());
+        if let Some(executable) = string("CFBundleExecutable") {
+            self.lint_executable(&contents.join("MacOS").join(executable))?;
+        }
+        if let Some(icon) = string("CFBundleIconFile") {
+            let mut icon_path = contents.join("Resources").join(icon);
+            if icon_path.extension().is_none() {
+                icon_path.set_extension("icns");
+            }
+            if !icon_path.is_file() {
+                self.report(
+                    &icon_path,
+                    Severity::Error,
+                    format!("Icon file \"{}\" (CFBundleIconFile) does not exist", icon),
+                );
+            }
+        }
+        if string("CFBundleIconName").is_some() {
+            let assets = contents.join("Resources").join("Assets.car");
+            if !assets.is_file() {
+                self.report(
+                    &assets,
+                    Severity::Warning,
+                    "CFBundleIconName is set but there is no asset catalog".into(),
+                );
+            }
+        }
+        if let Some(identifier) = string("CFBundleIdentifier") {
+            if !is_valid_identifier(identifier) {
+                self.report(
+                    &info_plist,
+                    Severity::Error,
+                    format!(
+                        "CFBundleIdentifier \"{}\" is not a valid reverse-DNS string",
+                        identifier
+                    ),
+                );
+            }
+        }
+        for key in &["CFBundleShortVersionString", "CFBundleVersion"] {
+            if let Some(version) = string(key) {
+                if !is_valid_version(version) {
+                    self.report(
+                        &info_plist,
+                        Severity::Error,
+                        format!(
+                            "{} \"{}\" must be one to three period-separated integers",
+                            key, version
+                        ),
+                    );
+                }
+            }
+        }
+        if let Some(version) = string("LSMinimumSystemVersion") {
+            if version.parse::<Version>().is_err() {
+                self.report(
+                    &info_plist,
+                    Severity::Error,
+                    format!(
+                        "LSMinimumSystemVersion \"{}\" is not a valid version",
+                        version
+                    ),
+                );
+            }
+        }
+        let extension = bundle_path.extension().map(|e| e.to_string_lossy());
+        let expected_type = match extension.as_deref() {
+            Some("app") => Some("APPL"),
+            Some("xpc") => Some("XPC!"),
+            Some("framework") => Some("FMWK"),
+            _ => None,
+        };
+        if let (Some(expected), Some(package_type)) = (expected_type, string("CFBundlePackageType"))
+        {
+            if package_type != expected {
+                self.report(
+                    &info_plist,
+                    Severity::Warning,
+                    format!(
+                        "CFBundlePackageType is \"{}\", expected \"{}\"",
+                        package_type, expected
+                    ),
+                );
+            }
+        }
+
+        for dir in NESTED_BUNDLE_DIRS {
+            let dir = contents.join(dir);
+            if !dir.is_dir() {
+                continue;
+            }
+            for nested in sorted_entries(&dir)? {
+                let is_bundle = nested
+                    .extension()
+                    .map(|e| e == "app" || e == "xpc")
+                    .unwrap_or(false);
+                if is_bundle && nested.is_dir() {
+                    self.lint_bundle(&nested)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn lint_keys(&mut self, info_plist: &Path, info: &plist::Dictionary) {
+        for (key, severity) in REQUIRED_KEYS {
+            if !info.contains_key(key) {
+                self.report(
+                    info_plist,
+                    *severity,
+                    format!("Missing required key {}", key),
+                );
+            }
+        }
+        for (key, expected) in KEY_TYPES {
+            let actual = match info.get(key) {
+                Some(plist::Value::String(_)) => ValueType::String,
+                Some(plist::Value::Boolean(_)) => ValueType::Boolean,
+                Some(plist::Value::Array(_)) => ValueType::Array,
+                Some(plist::Value::Dictionary(_)) => ValueType::Dictionary,
+                Some(_) => {
+                    self.report(
+                        info_plist,
+                        Severity::Error,
+                        format!("{} has unexpected type", key),
+                    );
+                    continue;
+                }
+                None => continue,
+            };
+            if actual != *expected {
+                self.report(
+                    info_plist,
+                    Severity::Error,
+                    format!("{} must be {}", key, type_name(*expected)),
+                );
+            }
+        }
+    }
+
+    fn lint_executable(&mut self, executable: &Path) -> ToolResult<()> {
+        if !executable.is_file() {
+            self.report(
+                executable,
+                Severity::Error,
+                "Bundle executable (CFBundleExecutable) does not exist".into(),
+            );
+            return Ok(());
+        }
+        match classify_binary(executable)? {
+            Some(info) if info.binary_type == BinaryType::Executable => {}
+            Some(info) => self.report(
+                executable,
+                Severity::Error,
+                format!(
+                    "Bundle executable is {:?} instead of Mach-O executable",
+                    info.binary_type
+                ),
+            ),
+            None => self.report(
+                executable,
+                Severity::Error,
+                "Bundle executable is not a Mach-O binary".into(),
+            ),
+        }
+        Ok(())
+    }
+
+    // Bundle root must only contain Contents (code signing fails otherwise); Files
+    // directly in Contents other than Info.plist and PkgInfo are likely misplaced.
+    fn lint_structure(&mut self, bundle_path: &Path) -> ToolResult<()> {
+        for entry in sorted_entries(bundle_path)? {
+            if entry.file_name().unwrap() != "Contents" {
+                self.report(
+                    &entry,
+                    Severity::Error,
+                    "Stray file at bundle root; Only Contents folder is allowed".into(),
+                );
+            }
+        }
+        for entry in sorted_entries(&bundle_path.join("Contents"))? {
+            let name = entry.file_name().unwrap().to_string_lossy().to_string();
+            if !entry.is_dir() && !CONTENTS_FILES.contains(&name.as_str()) {
+                self.report(
+                    &entry,
+                    Severity::Warning,
+                    "Unexpected file in Contents; Resources belong to Contents/Resources".into(),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn sorted_entries(dir: &Path) -> ToolResult<Vec<PathBuf>> {
+    let mut res = Vec::new();
+    for entry in dir
+        .read_dir()
+        .wrap_error(FileOperation::ReadDir, || dir.into())?
+    {
+        let entry = entry.wrap_error(FileOperation::Read, || dir.into())?;
+        res.push(entry.path());
+    }
+    res.sort();
+    Ok(res)
+}
+
+fn type_name(value_type: ValueType) -> &'static str {
+    match value_type {
+        ValueType::String => "a string",
+        ValueType::Boolean => "a boolean",
+        ValueType::Array => "an array",
+        ValueType::Dictionary => "a dictionary",
+    }
+}
+
+// Reverse-DNS string; At least two components of alphanumerics, hyphens and periods
+fn is_valid_identifier(identifier: &str) -> bool {
+    let components: Vec<_> = identifier.split('.').collect();
+    components.len() >= 2
+        && components
+            .iter()
+            .all(|c| !c.is_empty() && c.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+}
+
+// One to three period-separated non-negative integers
+fn is_valid_version(version: &str) -> bool {
+    let components: Vec<_> = version.split('.').collect();
+    components.len() <= 3
+        && components
+            .iter()
+            .all(|c| !c.is_empty() && c.chars().all(|c| c.is_ascii_digit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_identifiers() {
+        assert!(is_valid_identifier("com.example.app"));
+        assert!(is_valid_identifier("com.example-company.My-App2"));
+        assert!(!is_valid_identifier("app"));
+        assert!(!is_valid_identifier(""));
+        assert!(!is_valid_identifier("com..app"));
+        assert!(!is_valid_identifier("com.example."));
+        assert!(!is_valid_identifier("com.example.my_app"));
+        assert!(!is_valid_identifier("com.example.my app"));
+        assert!(!is_valid_identifier("com.exämple.app"));
+    }
+
+    #[test]
+    fn validates_versions() {
+        assert!(is_valid_version("1"));
+        assert!(is_valid_version("1.2"));
+        assert!(is_valid_version("10.20.30"));
+        assert!(!is_valid_version(""));
+        assert!(!is_valid_version("1.2.3.4"));
+        assert!(!is_valid_version("1..2"));
+        assert!(!is_valid_version("1.2."));
+        assert!(!is_valid_version("1.0.0-beta"));
+        assert!(!is_valid_version("v1.0"));
+        assert!(!is_valid_version("-1"));
+    }
+}
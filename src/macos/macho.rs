@@ -0,0 +1,935 @@
+use std::{collections::HashSet, convert::TryInto, fmt::Display, path::Path, str::FromStr};
+
+use crate::error::{FileOperation, IOResultExt, ToolError, ToolResult};
+
+pub const MH_MAGIC: u32 = 0xfeedface;
+pub const MH_MAGIC_64: u32 = 0xfeedfacf;
+pub const FAT_MAGIC: u32 = 0xcafebabe;
+pub const FAT_MAGIC_64: u32 = 0xcafebabf;
+pub const AR_MAGIC: &[u8] = b"!<arch>\n";
+
+pub const LC_REQ_DYLD: u32 = 0x80000000;
+pub const LC_SEGMENT: u32 = 0x1;
+pub const LC_SYMTAB: u32 = 0x2;
+pub const LC_SEGMENT_64: u32 = 0x19;
+pub const LC_LOAD_DYLIB: u32 = 0xc;
+pub const LC_ID_DYLIB: u32 = 0xd;
+pub const LC_LOAD_WEAK_DYLIB: u32 = 0x18 | LC_REQ_DYLD;
+pub const LC_RPATH: u32 = 0x1c | LC_REQ_DYLD;
+pub const LC_CODE_SIGNATURE: u32 = 0x1d;
+pub const LC_REEXPORT_DYLIB: u32 = 0x1f | LC_REQ_DYLD;
+pub const LC_LAZY_LOAD_DYLIB: u32 = 0x20;
+pub const LC_LOAD_UPWARD_DYLIB: u32 = 0x23 | LC_REQ_DYLD;
+pub const LC_DYLD_INFO: u32 = 0x22;
+pub const LC_DYLD_INFO_ONLY: u32 = 0x22 | LC_REQ_DYLD;
+pub const LC_VERSION_MIN_MACOSX: u32 = 0x24;
+pub const LC_BUILD_VERSION: u32 = 0x32;
+pub const LC_DYLD_EXPORTS_TRIE: u32 = 0x33 | LC_REQ_DYLD;
+
+pub const PLATFORM_MACOS: u32 = 1;
+
+pub const MH_OBJECT: u32 = 0x1;
+pub const MH_EXECUTE: u32 = 0x2;
+pub const MH_DYLIB: u32 = 0x6;
+pub const MH_BUNDLE: u32 = 0x8;
+pub const MH_DSYM: u32 = 0xa;
+pub const MH_KEXT_BUNDLE: u32 = 0xb;
+
+pub const MH_TWOLEVEL: u32 = 0x80;
+
+// Special library ordinals of undefined symbols
+pub const SELF_LIBRARY_ORDINAL: u8 = 0x0;
+pub const DYNAMIC_LOOKUP_ORDINAL: u8 = 0xfe;
+pub const EXECUTABLE_ORDINAL: u8 = 0xff;
+
+const N_STAB: u8 = 0xe0;
+const N_TYPE: u8 = 0x0e;
+const N_EXT: u8 = 0x01;
+const N_UNDF: u8 = 0x0;
+const N_SECT: u8 = 0xe;
+const N_INDR: u8 = 0xa;
+const N_WEAK_REF: u16 = 0x40;
+
+const SECTION_TYPE: u32 = 0xff;
+const S_ZEROFILL: u32 = 0x1;
+const S_GB_ZEROFILL: u32 = 0xc;
+const S_THREAD_LOCAL_ZEROFILL: u32 = 0x12;
+
+const CPU_ARCH_ABI64: u32 = 0x01000000;
+const CPU_TYPE_X86: u32 = 7;
+const CPU_TYPE_ARM: u32 = 12;
+const CPU_TYPE_POWERPC: u32 = 18;
+const CPU_SUBTYPE_MASK: u32 = 0xff000000;
+const CPU_SUBTYPE_ARM64E: u32 = 2;
+
+// Version encoded as xxxx.yy.zz nibbles (as used in load commands)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version(pub u32);
+
+impl Version {
+    pub fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Version((major << 16) | ((minor & 0xff) << 8) | (patch & 0xff))
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (major, minor, patch) = (self.0 >> 16, (self.0 >> 8) & 0xff, self.0 & 0xff);
+        if patch == 0 {
+            write!(f, "{}.{}", major, minor)
+        } else {
+            write!(f, "{}.{}.{}", major, minor, patch)
+        }
+    }
+}
+
+impl FromStr for Version {
+    type Err = ToolError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s
+            .trim()
+            .split('.')
+            .map(|p| p.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .ok()
+            .filter(|p| !p.is_empty() && p.len() <= 3)
+            .ok_or_else(|| ToolError::OtherError(format!("Invalid version \"{}\"", s)))?;
+        let part = |i: usize| parts.get(i).cloned().unwrap_or(0);
+        Ok(Version::new(part(0), part(1), part(2)))
+    }
+}
+
+// Mach-O file; Either thin (single slice) or fat (universal) binary.
+pub struct MachFile {
+    data: Vec<u8>,
+    slices: Vec<(usize, usize)>,
+    // CPU type and subtype from fat header
+    fat_archs: Vec<(u32, u32)>,
+}
+
+impl MachFile {
+    // Reads Mach-O file at given path. Returns None if the file is not a Mach-O file.
+    pub fn read(path: &Path) -> ToolResult<Option<MachFile>> {
+        let data = std::fs::read(path).wrap_error(FileOperation::Read, || path.into())?;
+        Self::parse(data)
+            .map_err(|e| ToolError::OtherError(format!("Malformed Mach-O file {:?}: {}", path, e)))
+    }
+
+    pub fn parse(data: Vec<u8>) -> Result<Option<MachFile>, String> {
+        let magic = match read_u32(&data, 0, false) {
+            Some(magic) => magic,
+            None => return Ok(None),
+        };
+        let (slices, fat_archs) = match magic {
+            FAT_MAGIC | FAT_MAGIC_64 => {
+                let is_64 = magic == FAT_MAGIC_64;
+                let count = read_u32(&data, 4, false).ok_or("truncated fat header")? as usize;
+                let arch_size = if is_64 { 32 } else { 20 };
+                let mut slices = Vec::new();
+                let mut fat_archs = Vec::new();
+                for i in 0..count {
+                    let base = 8 + i * arch_size;
+                    let (offset, size) = if is_64 {
+                        (
+                            read_u64(&data, base + 8, false),
+                            read_u64(&data, base + 16, false),
+                        )
+                    } else {
+                        (
+                            read_u32(&data, base + 8, false).map(u64::from),
+                            read_u32(&data, base + 12, false).map(u64::from),
+                        )
+                    };
+                    let (offset, size) = match (offset, size) {
+                        (Some(offset), Some(size)) => (offset as usize, size as usize),
+                        _ => return Err("truncated fat arch".into()),
+                    };
+                    if offset.checked_add(size).map(|e| e > data.len()) != Some(false) {
+                        return Err("fat arch out of bounds".into());
+                    }
+                    slices.push((offset, size));
+                    fat_archs.push((
+                        read_u32(&data, base, false).unwrap_or(0),
+                        read_u32(&data, base + 4, false).unwrap_or(0),
+                    ));
+                }
+                (slices, fat_archs)
+            }
+            _ => (vec![(0, data.len())], Vec::new()),
+        };
+        let res = MachFile {
+            data,
+            slices,
+            fat_archs,
+        };
+        // Fat binaries may contain slices that are not Mach-O (i.e. static archives);
+        // Thin file is only Mach-O if it has Mach-O magic.
+        if magic != FAT_MAGIC && magic != FAT_MAGIC_64 && res.slices().next().is_none() {
+            return Ok(None);
+        }
+        for slice in res.slices() {
+            slice.validate()?;
+        }
+        Ok(Some(res))
+    }
+
+    pub fn is_fat(&self) -> bool {
+        matches!(
+            read_u32(&self.data, 0, false),
+            Some(FAT_MAGIC | FAT_MAGIC_64)
+        )
+    }
+
+    // Mach-O slices of this file
+    pub fn slices(&self) -> impl Iterator<Item = Slice<'_>> {
+        self.slices
+            .iter()
+            .filter_map(move |(offset, size)| Slice::new(&self.data[*offset..*offset + *size]))
+    }
+
+    // Architectures of all slices (including non Mach-O ones)
+    pub fn archs(&self) -> Vec<String> {
+        if self.is_fat() {
+            self.fat_archs
+                .iter()
+                .map(|(cpu_type, cpu_subtype)| arch_name(*cpu_type, *cpu_subtype))
+                .collect()
+        } else {
+            self.slices().map(|s| s.arch_name()).collect()
+        }
+    }
+
+    // Raw content of all slices (including non Mach-O ones)
+    pub fn raw_slices(&self) -> impl Iterator<Item = &[u8]> {
+        self.slices
+            .iter()
+            .map(move |(offset, size)| &self.data[*offset..*offset + *size])
+    }
+}
+
+pub struct Slice<'a> {
+    data: &'a [u8],
+    le: bool,
+    is_64: bool,
+}
+
+pub struct LoadCommand<'a> {
+    pub cmd: u32,
+    // Entire command data including cmd and cmdsize
+    pub data: &'a [u8],
+    le: bool,
+}
+
+impl<'a> LoadCommand<'a> {
+    pub fn u32_at(&self, offset: usize) -> Option<u32> {
+        read_u32(self.data, offset, self.le)
+    }
+
+    pub fn u64_at(&self, offset: usize) -> Option<u64> {
+        read_u64(self.data, offset, self.le)
+    }
+
+    // Reads lc_str at given offset within command
+    pub fn string_at(&self, offset: usize) -> Option<String> {
+        let str_offset = self.u32_at(offset)? as usize;
+        let bytes = self.data.get(str_offset..)?;
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        Some(String::from_utf8_lossy(&bytes[..end]).into())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub segment_name: String,
+    pub section_name: String,
+    pub size: u64,
+    pub offset: u32,
+    pub flags: u32,
+}
+
+impl Section {
+    pub fn is_zerofill(&self) -> bool {
+        matches!(
+            self.flags & SECTION_TYPE,
+            S_ZEROFILL | S_GB_ZEROFILL | S_THREAD_LOCAL_ZEROFILL
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DylibReference {
+    pub cmd: u32,
+    pub name: String,
+}
+
+impl DylibReference {
+    pub fn is_weak(&self) -> bool {
+        self.cmd == LC_LOAD_WEAK_DYLIB
+    }
+}
+
+// Undefined external symbol from symbol table
+#[derive(Debug, Clone)]
+pub struct ImportedSymbol {
+    pub name: String,
+    // Two-level namespace library ordinal (1-based index into dylib_references or
+    // one of the special ordinals)
+    pub library_ordinal: u8,
+    pub weak: bool,
+}
+
+// Symbol table entry
+struct Nlist {
+    strx: u32,
+    n_type: u8,
+    desc: u16,
+    value: u64,
+}
+
+impl<'a> Slice<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let (le, is_64) = match read_u32(data, 0, false)? {
+            MH_MAGIC => (false, false),
+            MH_MAGIC_64 => (false, true),
+            m if m.swap_bytes() == MH_MAGIC => (true, false),
+            m if m.swap_bytes() == MH_MAGIC_64 => (true, true),
+            _ => return None,
+        };
+        Some(Slice { data, le, is_64 })
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let end = self.header_size() + self.size_of_cmds() as usize;
+        if self.data.len() < self.header_size() || end > self.data.len() {
+            return Err("truncated header".into());
+        }
+        let mut offset = self.header_size();
+        for _ in 0..self.number_of_cmds() {
+            let size = self.u32_at(offset + 4).ok_or("truncated load command")? as usize;
+            if size < 8 || offset + size > end {
+                return Err("invalid load command size".into());
+            }
+            offset += size;
+        }
+        Ok(())
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn u32_at(&self, offset: usize) -> Option<u32> {
+        read_u32(self.data, offset, self.le)
+    }
+
+    pub fn cpu_type(&self) -> u32 {
+        self.u32_at(4).unwrap_or(0)
+    }
+
+    pub fn cpu_subtype(&self) -> u32 {
+        self.u32_at(8).unwrap_or(0)
+    }
+
+    pub fn file_type(&self) -> u32 {
+        self.u32_at(12).unwrap_or(0)
+    }
+
+    pub fn number_of_cmds(&self) -> u32 {
+        self.u32_at(16).unwrap_or(0)
+    }
+
+    pub fn size_of_cmds(&self) -> u32 {
+        self.u32_at(20).unwrap_or(0)
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.u32_at(24).unwrap_or(0)
+    }
+
+    pub fn header_size(&self) -> usize {
+        if self.is_64 {
+            32
+        } else {
+            28
+        }
+    }
+
+    pub fn arch_name(&self) -> String {
+        arch_name(self.cpu_type(), self.cpu_subtype())
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.load_commands().any(|c| c.cmd == LC_CODE_SIGNATURE)
+    }
+
+    pub fn load_commands(&self) -> impl Iterator<Item = LoadCommand<'a>> {
+        let data = self.data;
+        let le = self.le;
+        let mut offset = self.header_size();
+        (0..self.number_of_cmds()).map(move |_| {
+            // Bounds were verified in validate()
+            let cmd = read_u32(data, offset, le).unwrap();
+            let size = read_u32(data, offset + 4, le).unwrap() as usize;
+            let res = LoadCommand {
+                cmd,
+                data: &data[offset..offset + size],
+                le,
+            };
+            offset += size;
+            res
+        })
+    }
+
+    // Minimum macOS version from LC_BUILD_VERSION or LC_VERSION_MIN_MACOSX
+    pub fn minimum_os_version(&self) -> Option<Version> {
+        self.load_commands().find_map(|c| match c.cmd {
+            LC_BUILD_VERSION if c.u32_at(8) == Some(PLATFORM_MACOS) => c.u32_at(12).map(Version),
+            LC_VERSION_MIN_MACOSX => c.u32_at(8).map(Version),
+            _ => None,
+        })
+    }
+
+    // Dependencies in load order (which is also the order used for library ordinals)
+    pub fn dylib_references(&self) -> Vec<DylibReference> {
+        self.load_commands()
+            .filter(|c| {
+                matches!(
+                    c.cmd,
+                    LC_LOAD_DYLIB
+                        | LC_LOAD_WEAK_DYLIB
+                        | LC_REEXPORT_DYLIB
+                        | LC_LAZY_LOAD_DYLIB
+                        | LC_LOAD_UPWARD_DYLIB
+                )
+            })
+            .filter_map(|c| {
+                Some(DylibReference {
+                    cmd: c.cmd,
+                    name: c.string_at(8)?,
+                })
+            })
+            .collect()
+    }
+
+    pub fn sections(&self) -> Vec<Section> {
+        let mut res = Vec::new();
+        for c in self.load_commands() {
+            let (header_size, section_size) = match c.cmd {
+                LC_SEGMENT_64 => (72, 80),
+                LC_SEGMENT => (56, 68),
+                _ => continue,
+            };
+            let is_64 = c.cmd == LC_SEGMENT_64;
+            let nsects = c.u32_at(if is_64 { 64 } else { 48 }).unwrap_or(0) as usize;
+            for i in 0..nsects {
+                let base = header_size + i * section_size;
+                res.extend(parse_section(&c, base, is_64));
+            }
+        }
+        res
+    }
+
+    // Space available for additional load commands; This is the gap between end of
+    // load commands and the first section content.
+    pub fn header_padding(&self) -> u64 {
+        let end_of_commands = (self.header_size() + self.size_of_cmds() as usize) as u64;
+        let first_section = self
+            .sections()
+            .iter()
+            .filter(|s| !s.is_zerofill() && s.offset != 0 && s.size != 0)
+            .map(|s| s.offset as u64)
+            .min()
+            .unwrap_or(self.data.len() as u64);
+        first_section.saturating_sub(end_of_commands)
+    }
+
+    // Size of load command with given string payload (dylib or rpath command)
+    // after alignment.
+    pub fn string_command_size(&self, header_size: usize, string: &str) -> u64 {
+        let alignment = if self.is_64 { 8 } else { 4 };
+        let size = header_size + string.len() + 1;
+        (size.div_ceil(alignment) * alignment) as u64
+    }
+
+    pub fn install_name(&self) -> Option<String> {
+        self.load_commands()
+            .find(|c| c.cmd == LC_ID_DYLIB)
+            .and_then(|c| c.string_at(8))
+    }
+
+    pub fn rpaths(&self) -> Vec<String> {
+        self.load_commands()
+            .filter(|c| c.cmd == LC_RPATH)
+            .filter_map(|c| c.string_at(8))
+            .collect()
+    }
+
+    pub fn is_two_level(&self) -> bool {
+        self.flags() & MH_TWOLEVEL != 0
+    }
+
+    // Undefined external symbols; Common symbols (undefined with non-zero value)
+    // are not included.
+    pub fn imported_symbols(&self) -> Vec<ImportedSymbol> {
+        self.symbols()
+            .filter(|(_, n)| {
+                n.n_type & N_STAB == 0
+                    && n.n_type & N_TYPE == N_UNDF
+                    && n.n_type & N_EXT != 0
+                    && n.value == 0
+            })
+            .map(|(name, n)| ImportedSymbol {
+                name,
+                library_ordinal: (n.desc >> 8) as u8,
+                weak: n.desc & N_WEAK_REF != 0,
+            })
+            .collect()
+    }
+
+    // Symbols exported by this image. Read from exports trie, or from symbol
+    // table for binaries without dyld info.
+    pub fn exported_symbols(&self) -> Result<Vec<String>, String> {
+        let trie = self.load_commands().find_map(|c| match c.cmd {
+            LC_DYLD_INFO | LC_DYLD_INFO_ONLY => Some((c.u32_at(40)?, c.u32_at(44)?)),
+            LC_DYLD_EXPORTS_TRIE => Some((c.u32_at(8)?, c.u32_at(12)?)),
+            _ => None,
+        });
+        match trie {
+            Some((_, 0)) => Ok(Vec::new()),
+            Some((offset, size)) => {
+                let start = offset as usize;
+                let mut res = Vec::new();
+                start
+                    .checked_add(size as usize)
+                    .and_then(|end| self.data.get(start..end))
+                    .and_then(|trie| {
+                        parse_export_trie(
+                            trie,
+                            0,
+                            &mut Vec::new(),
+                            &mut res,
+                            &mut HashSet::new(),
+                            0,
+                        )
+                    })
+                    .ok_or_else(|| "malformed exports trie".to_string())?;
+                Ok(res)
+            }
+            None => Ok(self
+                .symbols()
+                .filter(|(_, n)| {
+                    n.n_type & N_STAB == 0
+                        && n.n_type & N_EXT != 0
+                        && matches!(n.n_type & N_TYPE, N_SECT | N_INDR)
+                })
+                .map(|(name, _)| name)
+                .collect()),
+        }
+    }
+
+    fn symbols(&self) -> impl Iterator<Item = (String, Nlist)> + 'a {
+        let symtab = self.load_commands().find(|c| c.cmd == LC_SYMTAB);
+        let (symoff, nsyms, stroff, strsize) = symtab
+            .and_then(|c| Some((c.u32_at(8)?, c.u32_at(12)?, c.u32_at(16)?, c.u32_at(20)?)))
+            .unwrap_or_default();
+        let data = self.data;
+        let le = self.le;
+        let is_64 = self.is_64;
+        let entry_size = if is_64 { 16 } else { 12 };
+        let strings = data
+            .get(stroff as usize..(stroff as usize).saturating_add(strsize as usize))
+            .unwrap_or(&[]);
+        (0..nsyms as usize).filter_map(move |i| {
+            let offset = symoff as usize + i * entry_size;
+            let entry = data.get(offset..offset + entry_size)?;
+            let nlist = Nlist {
+                strx: read_u32(entry, 0, le)?,
+                n_type: entry[4],
+                desc: if le {
+                    u16::from_le_bytes([entry[6], entry[7]])
+                } else {
+                    u16::from_be_bytes([entry[6], entry[7]])
+                },
+                value: if is_64 {
+                    read_u64(entry, 8, le)?
+                } else {
+                    read_u32(entry, 8, le)? as u64
+                },
+            };
+            let name = strings.get(nlist.strx as usize..)?;
+            let end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+            Some((String::from_utf8_lossy(&name[..end]).into(), nlist))
+        })
+    }
+}
+
+// Walks the exports trie collecting symbol names. Returns None if the trie is
+// malformed; Every node of a valid trie is reached exactly once, so a node visited
+// again means the trie is cyclic. Depth is limited to bound recursion.
+fn parse_export_trie(
+    trie: &[u8],
+    offset: usize,
+    prefix: &mut Vec<u8>,
+    res: &mut Vec<String>,
+    visited: &mut HashSet<usize>,
+    depth: usize,
+) -> Option<()> {
+    if depth > 128 || !visited.insert(offset) {
+        return None;
+    }
+    // Sizes and offsets come from the file, so all arithmetic is checked
+    let mut pos = offset;
+    let terminal_size: usize = read_uleb128(trie, &mut pos)?.try_into().ok()?;
+    // Any terminal node is an exported name, including re-exports and resolvers
+    if terminal_size > 0 {
+        res.push(String::from_utf8_lossy(prefix).into());
+    }
+    pos = pos.checked_add(terminal_size)?;
+    let child_count = *trie.get(pos)?;
+    pos = pos.checked_add(1)?;
+    for _ in 0..child_count {
+        let edge = trie.get(pos..)?;
+        let end = edge.iter().position(|b| *b == 0)?;
+        let prefix_len = prefix.len();
+        prefix.extend_from_slice(&edge[..end]);
+        pos = pos.checked_add(end + 1)?;
+        let child: usize = read_uleb128(trie, &mut pos)?.try_into().ok()?;
+        parse_export_trie(trie, child, prefix, res, visited, depth + 1)?;
+        prefix.truncate(prefix_len);
+    }
+    Some(())
+}
+
+fn read_uleb128(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut res = 0u64;
+    let mut shift = 0u32;
+    loop {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        if shift < 64 {
+            res |= ((byte & 0x7f) as u64) << shift;
+        }
+        shift = shift.saturating_add(7);
+        if byte & 0x80 == 0 {
+            return Some(res);
+        }
+    }
+}
+
+fn arch_name(cpu_type: u32, cpu_subtype: u32) -> String {
+    let subtype = cpu_subtype & !CPU_SUBTYPE_MASK;
+    match cpu_type {
+        CPU_TYPE_X86 => "i386".into(),
+        t if t == CPU_TYPE_X86 | CPU_ARCH_ABI64 => {
+            if subtype == 8 {
+                "x86_64h".into()
+            } else {
+                "x86_64".into()
+            }
+        }
+        CPU_TYPE_ARM => "arm".into(),
+        t if t == CPU_TYPE_ARM | CPU_ARCH_ABI64 => {
+            if subtype == CPU_SUBTYPE_ARM64E {
+                "arm64e".into()
+            } else {
+                "arm64".into()
+            }
+        }
+        CPU_TYPE_POWERPC => "ppc".into(),
+        t => format!("cpu_{:#x}", t),
+    }
+}
+
+fn parse_section(c: &LoadCommand, base: usize, is_64: bool) -> Option<Section> {
+    let (size, offset, flags) = if is_64 {
+        (
+            c.u64_at(base + 40)?,
+            c.u32_at(base + 48)?,
+            c.u32_at(base + 64)?,
+        )
+    } else {
+        (
+            c.u32_at(base + 36)? as u64,
+            c.u32_at(base + 40)?,
+            c.u32_at(base + 56)?,
+        )
+    };
+    Some(Section {
+        section_name: fixed_string(c.data.get(base..base + 16)?),
+        segment_name: fixed_string(c.data.get(base + 16..base + 32)?),
+        size,
+        offset,
+        flags,
+    })
+}
+
+fn fixed_string(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into()
+}
+
+fn read_u32(data: &[u8], offset: usize, le: bool) -> Option<u32> {
+    let bytes: [u8; 4] = data.get(offset..offset + 4)?.try_into().ok()?;
+    Some(if le {
+        u32::from_le_bytes(bytes)
+    } else {
+        u32::from_be_bytes(bytes)
+    })
+}
+
+fn read_u64(data: &[u8], offset: usize, le: bool) -> Option<u64> {
+    let bytes: [u8; 8] = data.get(offset..offset + 8)?.try_into().ok()?;
+    Some(if le {
+        u64::from_le_bytes(bytes)
+    } else {
+        u64::from_be_bytes(bytes)
+    })
+}
+
+// Builders of small Mach-O files for unit tests
+#[cfg(test)]
+pub(super) mod test_fixtures {
+    use super::*;
+
+    pub const CPU_TYPE_ARM64: u32 = CPU_TYPE_ARM | CPU_ARCH_ABI64;
+    pub const CPU_TYPE_X86_64: u32 = CPU_TYPE_X86 | CPU_ARCH_ABI64;
+
+    // Thin 64-bit little endian Mach-O with given load commands
+    pub fn macho(cpu_type: u32, file_type: u32, commands: &[Vec<u8>]) -> Vec<u8> {
+        let cmds = commands.concat();
+        let header = [
+            MH_MAGIC_64,
+            cpu_type,
+            0,
+            file_type,
+            commands.len() as u32,
+            cmds.len() as u32,
+            0,
+            0,
+        ];
+        let mut data: Vec<u8> = header.iter().flat_map(|v| v.to_le_bytes()).collect();
+        data.extend(cmds);
+        data
+    }
+
+    // Load command with lc_str payload; `fields` is the number of u32 fields
+    // following the string offset (3 for dylib commands, 0 for rpath).
+    pub fn string_command(cmd: u32, fields: usize, string: &str) -> Vec<u8> {
+        let str_offset = 12 + fields * 4;
+        let size = (str_offset + string.len() + 1).div_ceil(8) * 8;
+        let mut data = Vec::new();
+        for v in [cmd, size as u32, str_offset as u32] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        data.resize(str_offset, 0);
+        data.extend_from_slice(string.as_bytes());
+        data.resize(size, 0);
+        data
+    }
+
+    pub fn build_version(minos: Version) -> Vec<u8> {
+        [LC_BUILD_VERSION, 24, PLATFORM_MACOS, minos.0, minos.0, 0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{test_fixtures::*, *};
+
+    #[test]
+    fn parses_thin_dylib() {
+        let data = macho(
+            CPU_TYPE_ARM64,
+            MH_DYLIB,
+            &[
+                string_command(LC_ID_DYLIB, 3, "@rpath/libfoo.dylib"),
+                string_command(LC_LOAD_DYLIB, 3, "/usr/lib/libSystem.B.dylib"),
+                string_command(LC_LOAD_WEAK_DYLIB, 3, "@rpath/libbar.dylib"),
+                string_command(LC_RPATH, 0, "@loader_path/../Frameworks"),
+                build_version(Version::new(11, 0, 0)),
+            ],
+        );
+        let file = MachFile::parse(data).unwrap().unwrap();
+        assert!(!file.is_fat());
+        assert_eq!(file.archs(), vec!["arm64"]);
+
+        let slice = file.slices().next().unwrap();
+        assert_eq!(slice.file_type(), MH_DYLIB);
+        assert_eq!(slice.install_name().as_deref(), Some("@rpath/libfoo.dylib"));
+        assert_eq!(slice.rpaths(), vec!["@loader_path/../Frameworks"]);
+        assert_eq!(slice.minimum_os_version(), Some(Version::new(11, 0, 0)));
+        assert!(!slice.is_signed());
+
+        let references: Vec<_> = slice
+            .dylib_references()
+            .into_iter()
+            .map(|r| (r.name.clone(), r.is_weak()))
+            .collect();
+        assert_eq!(
+            references,
+            vec![
+                ("/usr/lib/libSystem.B.dylib".to_string(), false),
+                ("@rpath/libbar.dylib".to_string(), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_fat_file() {
+        let slices = [
+            (CPU_TYPE_X86_64, macho(CPU_TYPE_X86_64, MH_EXECUTE, &[])),
+            (CPU_TYPE_ARM64, macho(CPU_TYPE_ARM64, MH_EXECUTE, &[])),
+        ];
+        let mut data = Vec::new();
+        data.extend_from_slice(&FAT_MAGIC.to_be_bytes());
+        data.extend_from_slice(&(slices.len() as u32).to_be_bytes());
+        let mut offset = 8 + 20 * slices.len();
+        for (cpu_type, slice) in &slices {
+            for v in [*cpu_type, 0, offset as u32, slice.len() as u32, 0] {
+                data.extend_from_slice(&v.to_be_bytes());
+            }
+            offset += slice.len();
+        }
+        for (_, slice) in &slices {
+            data.extend_from_slice(slice);
+        }
+
+        let file = MachFile::parse(data).unwrap().unwrap();
+        assert!(file.is_fat());
+        assert_eq!(file.archs(), vec!["x86_64", "arm64"]);
+        let file_types: Vec<_> = file.slices().map(|s| s.file_type()).collect();
+        assert_eq!(file_types, vec![MH_EXECUTE, MH_EXECUTE]);
+    }
+
+    #[test]
+    fn ignores_other_files() {
+        assert!(MachFile::parse(Vec::new()).unwrap().is_none());
+        assert!(MachFile::parse(b"#!/bin/sh\n".to_vec()).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        // Truncated header
+        let data = macho(CPU_TYPE_ARM64, MH_EXECUTE, &[]);
+        assert!(MachFile::parse(data[..16].to_vec()).is_err());
+
+        // Load command size smaller than command header
+        let mut command = string_command(LC_RPATH, 0, "@loader_path");
+        command[4..8].copy_from_slice(&4u32.to_le_bytes());
+        let data = macho(CPU_TYPE_ARM64, MH_EXECUTE, &[command]);
+        assert!(MachFile::parse(data).is_err());
+
+        // Fat slice out of bounds
+        let mut data = Vec::new();
+        for v in [FAT_MAGIC, 1, CPU_TYPE_ARM64, 0, 28, 1024, 0] {
+            data.extend_from_slice(&v.to_be_bytes());
+        }
+        assert!(MachFile::parse(data).is_err());
+    }
+
+    #[test]
+    fn parses_versions() {
+        assert_eq!("10.13".parse::<Version>().unwrap(), Version::new(10, 13, 0));
+        assert_eq!(
+            " 11.2.3 ".parse::<Version>().unwrap(),
+            Version::new(11, 2, 3)
+        );
+        assert!("".parse::<Version>().is_err());
+        assert!("1.2.3.4".parse::<Version>().is_err());
+        assert!("1.x".parse::<Version>().is_err());
+        assert_eq!(Version::new(10, 13, 0).to_string(), "10.13");
+        assert_eq!(Version::new(11, 2, 3).to_string(), "11.2.3");
+        assert!(Version::new(10, 15, 0) < Version::new(11, 0, 0));
+    }
+
+    #[test]
+    fn reads_uleb128() {
+        let data = [0xe5, 0x8e, 0x26, 0x7f];
+        let mut pos = 0;
+        assert_eq!(read_uleb128(&data, &mut pos), Some(624485));
+        assert_eq!(pos, 3);
+        assert_eq!(read_uleb128(&data, &mut pos), Some(0x7f));
+        // Unterminated value
+        let mut pos = 0;
+        assert_eq!(read_uleb128(&[0x80, 0x80], &mut pos), None);
+    }
+
+    fn parse_trie(trie: &[u8]) -> Option<Vec<String>> {
+        let mut res = Vec::new();
+        parse_export_trie(trie, 0, &mut Vec::new(), &mut res, &mut HashSet::new(), 0)?;
+        Some(res)
+    }
+
+    #[test]
+    fn parses_export_trie() {
+        #[rustfmt::skip]
+        let trie = [
+            // 0: root, two children
+            0x00, 0x02,
+            b'_', b'f', b'o', b'o', 0x00, 14,
+            b'_', b'b', b'a', b'r', 0x00, 18,
+            // 14: "_foo" terminal (flags, address), no children
+            0x02, 0x00, 0x10, 0x00,
+            // 18: "_bar" not terminal, one child
+            0x00, 0x01,
+            b'B', b'a', b'z', 0x00, 25,
+            // 25: "_barBaz" terminal, no children
+            0x01, 0x00, 0x00,
+        ];
+        assert_eq!(parse_trie(&trie).unwrap(), vec!["_foo", "_barBaz"]);
+    }
+
+    #[test]
+    fn handles_malformed_export_trie() {
+        // Terminal size overflowing usize
+        let trie = [
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00,
+        ];
+        assert_eq!(parse_trie(&trie), None);
+
+        // Child offset overflowing usize
+        #[rustfmt::skip]
+        let trie = [
+            0x00, 0x01, b'_', 0x00,
+            0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+        ];
+        assert_eq!(parse_trie(&trie), None);
+
+        // Child out of bounds
+        let trie = [0x00, 0x01, b'_', 0x00, 0x40];
+        assert_eq!(parse_trie(&trie), None);
+
+        // Child pointing back to root
+        let trie = [0x01, 0x00, 0x01, b'a', 0x00, 0x00];
+        assert_eq!(parse_trie(&trie), None);
+
+        // Two children pointing back to root (would be walked 2^depth times)
+        let trie = [0x00, 0x02, b'a', 0x00, 0x00, b'b', 0x00, 0x00];
+        assert_eq!(parse_trie(&trie), None);
+
+        // Chain of distinct nodes is walked up to the depth limit
+        let chain = |length: u16| {
+            let mut trie: Vec<u8> = (1..=length)
+                .flat_map(|i| {
+                    let next = i * 6;
+                    vec![
+                        0x00,
+                        0x01,
+                        b'a',
+                        0x00,
+                        (next & 0x7f) as u8 | 0x80,
+                        (next >> 7) as u8,
+                    ]
+                })
+                .collect();
+            trie.extend_from_slice(&[0x01, 0x00, 0x00]);
+            trie
+        };
+        assert_eq!(parse_trie(&chain(128)).unwrap(), vec!["a".repeat(128)]);
+        assert_eq!(parse_trie(&chain(129)), None);
+    }
+}
@@ -1,5 +1,23 @@
 pub mod bundle;
 pub mod codesign;
+mod consistency;
+pub mod diff;
+mod dlopen;
+mod flutter;
+pub mod icon;
+pub mod info_plist;
+pub mod inspect;
+mod install_name;
+pub mod lint;
+mod macho;
+mod native_assets;
 pub mod notarize;
+mod portability;
+mod resources;
+pub mod size;
+mod skeleton;
+mod swift;
+mod tbd;
 pub mod universal;
 mod utils;
+pub mod verify;
@@ -0,0 +1,160 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, warn};
+use serde::Deserialize;
+
+use crate::error::{FileOperation, IOResultExt, ToolError, ToolResult};
+
+pub const MANIFEST_FILE_NAME: &str = "native_assets.json";
+const NATIVE_ASSETS_DIR: &str = "native_assets";
+
+//
+// Manifest emitted by NativeShell build next to the executable. Paths are relative to
+// the manifest location (or absolute).
+//
+// {
+//   "code_assets": [
+//     { "type": "framework", "path": "native_assets/Foo.framework" },
+//     { "type": "dylib", "path": "native_assets/libbar.dylib" }
+//   ]
+// }
+//
+#[derive(Deserialize)]
+struct Manifest {
+    code_assets: Vec<CodeAsset>,
+}
+
+#[derive(Deserialize)]
+struct CodeAsset {
+    #[serde(rename = "type")]
+    kind: CodeAssetKind,
+    path: PathBuf,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum CodeAssetKind {
+    Framework,
+    Dylib,
+}
+
+// Returns binaries of native code assets that belong to given executable (which is
+// expected to be in original NativeShell build location).
+pub fn find_native_assets(executable: &Path) -> ToolResult<Vec<PathBuf>> {
+    let dir = executable.parent().unwrap();
+    let manifest_path = dir.join(MANIFEST_FILE_NAME);
+    if manifest_path.is_file() {
+        load_manifest(&manifest_path)
+    } else {
+        find_native_assets_fallback(&dir.join(NATIVE_ASSETS_DIR))
+    }
+}
+
+fn load_manifest(manifest_path: &Path) -> ToolResult<Vec<PathBuf>> {
+    debug!("Loading native assets manifest {:?}", manifest_path);
+    let content = fs::read_to_string(manifest_path)
+        .wrap_error(FileOperation::Read, || manifest_path.into())?;
+    let manifest: Manifest = serde_json::from_str(&content).map_err(|e| {
+        ToolError::OtherError(format!(
+            "Malformed native assets manifest {:?}: {}",
+            manifest_path, e
+        ))
+    })?;
+    let base = manifest_path.parent().unwrap();
+    manifest
+        .code_assets
+        .into_iter()
+        .map(|asset| {
+            let path = base.join(&asset.path);
+            let binary = match asset.kind {
+                CodeAssetKind::Framework => framework_binary(&path)?,
+                CodeAssetKind::Dylib if path.is_file() => Some(path.clone()),
+                CodeAssetKind::Dylib => None,
+            };
+            binary.ok_or_else(|| {
+                ToolError::OtherError(format!(
+                    "Native asset {:?} ({:?}) listed in {:?} not found",
+                    path, asset.kind, manifest_path
+                ))
+            })
+        })
+        .collect()
+}
+
+// Without manifest assume that all native assets are placed inside a "native_assets"
+// folder in the same directory as the executable.
+fn find_native_assets_fallback(native_assets_path: &Path) -> ToolResult<Vec<PathBuf>> {
+    let mut res = Vec::new();
+    if !native_assets_path.is_dir() {
+        return Ok(res);
+    }
+    for file in fs::read_dir(native_assets_path)
+        .wrap_error(FileOperation::ReadDir, || native_assets_path.into())?
+    {
+        let file = file.wrap_error(FileOperation::Read, || native_assets_path.into())?;
+        let path = file.path();
+        let extension = path.extension().map(|e| e.to_string_lossy().to_string());
+        let binary = match extension.as_deref() {
+            Some("framework") => framework_binary(&path)?,
+            Some("dylib") => Some(path.clone()),
+            _ => None,
+        };
+        match binary {
+            Some(binary) => res.push(binary),
+            None => warn!("Ignoring unrecognized native asset {:?}", path),
+        }
+    }
+    Ok(res)
+}
+
+// Returns binary path for framework; Versions/Current is resolved to actual version
+// so that the binary gets canonical install name.
+pub(super) fn framework_binary(framework: &Path) -> ToolResult<Option<PathBuf>> {
+    let stem = match framework.file_stem() {
+        Some(stem) => stem,
+        None => return Ok(None),
+    };
+    let versions = framework.join("Versions");
+    let current = versions.join("Current");
+    if current
+        .symlink_metadata()
+        .map(|m| m.file_type().is_symlink())
+        .unwrap_or(false)
+    {
+        let version = current
+            .read_link()
+            .wrap_error(FileOperation::ReadLink, || current.clone())?;
+        let binary = versions.join(version).join(stem);
+        if binary.is_file() {
+            return Ok(Some(binary));
+        }
+    }
+    let binary = versions.join("A").join(stem);
+    if binary.is_file() {
+        return Ok(Some(binary));
+    }
+    if versions.is_dir() {
+        for version in versions
+            .read_dir()
+            .wrap_error(FileOperation::ReadDir, || versions.clone())?
+        {
+            let version = version.wrap_error(FileOperation::Read, || versions.clone())?;
+            if version.file_name() == "Current" {
+                continue;
+            }
+            let binary = version.path().join(stem);
+            if binary.is_file() {
+                return Ok(Some(binary));
+            }
+        }
+    }
+    // Shallow framework
+    let binary = framework.join(stem);
+    if binary.is_file() {
+        return Ok(Some(binary));
+    }
+    Ok(None)
+}
@@ -6,35 +6,48 @@ use std::{
 };
 
 use log::{debug, info, trace};
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use scopeguard::defer;
 
 use crate::{
-    error::{IOResultExt, PlistResultExt, ToolError, ToolResult},
-    utils::run_command,
+    config::{apply, required, NotarizeConfig},
+    error::{PlistResultExt, ToolError, ToolResult},
+    utils::{run_command, temp_dir},
 };
 
 #[derive(clap::Parser)]
 pub struct Options {
     /// Path to self-contained code-signed bundle produced by
     /// the macos_bundle and macos_codesign commands
-    bundle_path: PathBuf,
+    bundle_path: Option<PathBuf>,
 
     /// App Store Connect API key. File system path to the private key.
-    #[clap(long, required = true)]
-    key: String,
+    #[clap(long)]
+    key: Option<String>,
 
     /// App Store Connect API Key ID. Usually 10 alphanumeric characters.
-    #[clap(long, required = true)]
-    key_id: String,
+    #[clap(long)]
+    key_id: Option<String>,
 
-    #[clap(long, required = true)]
+    #[clap(long)]
     /// App Store Connect API Issuer ID. UUID format.
-    issuer: String,
+    issuer: Option<String>,
+}
+
+impl Options {
+    pub fn apply_config(&mut self, config: &NotarizeConfig) {
+        apply(&mut self.bundle_path, &config.bundle_path);
+        apply(&mut self.key, &config.key);
+        apply(&mut self.key_id, &config.key_id);
+        apply(&mut self.issuer, &config.issuer);
+    }
 }
 
 pub struct Notarize {
     options: Options,
+    bundle_path: PathBuf,
+    key: String,
+    key_id: String,
+    issuer: String,
 }
 
 #[derive(Debug)]
@@ -45,11 +58,22 @@ struct NotarizationResult {
 
 impl Notarize {
     pub fn new(options: Options) -> Self {
-        Self { options }
+        Self {
+            options,
+            bundle_path: PathBuf::new(),
+            key: String::new(),
+            key_id: String::new(),
+            issuer: String::new(),
+        }
     }
 
-    pub fn perform(self) -> ToolResult<()> {
-        let temp_dir = self.temp_dir()?;
+    pub fn perform(mut self) -> ToolResult<()> {
+        self.bundle_path = required(&self.options.bundle_path, "bundle path")?;
+        self.key = required(&self.options.key, "--key")?;
+        self.key_id = required(&self.options.key_id, "--key-id")?;
+        self.issuer = required(&self.options.issuer, "--issuer")?;
+
+        let temp_dir = temp_dir()?;
         let compressed_path = self.compress_bundle(&temp_dir)?;
         let now = Instant::now();
         let result = self.notarize(&compressed_path)?;
@@ -78,11 +102,11 @@ impl Notarize {
             .arg("notarytool")
             .arg("submit")
             .arg("--key")
-            .arg(&self.options.key)
+            .arg(&self.key)
             .arg("--key-id")
-            .arg(&self.options.key_id)
+            .arg(&self.key_id)
             .arg("--issuer")
-            .arg(&self.options.issuer)
+            .arg(&self.issuer)
             .arg("--output-format")
             .arg("plist")
             .arg("--wait")
@@ -119,11 +143,11 @@ impl Notarize {
             .arg("log")
             .arg(id)
             .arg("--key")
-            .arg(&self.options.key)
+            .arg(&self.key)
             .arg("--key-id")
-            .arg(&self.options.key_id)
+            .arg(&self.key_id)
             .arg("--issuer")
-            .arg(&self.options.issuer);
+            .arg(&self.issuer);
 
         Ok(run_command(command, "xcrun")?.join("\n"))
     }
@@ -131,36 +155,16 @@ impl Notarize {
     fn staple(&self) -> ToolResult<()> {
         debug!("Stapling");
         let mut command = Command::new("xcrun");
-        command
-            .arg("stapler")
-            .arg("staple")
-            .arg(&self.options.bundle_path);
+        command.arg("stapler").arg("staple").arg(&self.bundle_path);
         run_command(command, "xcrun")?;
         Ok(())
     }
 
-    fn temp_dir(&self) -> ToolResult<PathBuf> {
-        let temp_dir = std::env::temp_dir();
-        let rand_string: String = thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(10)
-            .map(char::from)
-            .collect();
-        let path = temp_dir.join(rand_string);
-        fs::create_dir_all(&path)
-            .wrap_error(crate::error::FileOperation::CreateDir, || path.clone())?;
-        Ok(path)
-    }
-
     fn compress_bundle(&self, temp_dir: &Path) -> ToolResult<PathBuf> {
         debug!("Compressing bundle");
         let name = format!(
             "{}.zip",
-            self.options
-                .bundle_path
-                .file_name()
-                .unwrap()
-                .to_string_lossy()
+            self.bundle_path.file_name().unwrap().to_string_lossy()
         );
 
         let compressed_path = temp_dir.join(name);
@@ -171,7 +175,7 @@ impl Notarize {
             .arg("-k")
             .arg("--sequesterRsrc")
             .arg("--keepParent")
-            .arg(&self.options.bundle_path)
+            .arg(&self.bundle_path)
             .arg(&compressed_path);
 
         run_command(command, "ditto")?;
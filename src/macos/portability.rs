@@ -0,0 +1,282 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::error::{FileOperation, IOResultExt, ToolError, ToolResult};
+
+// Characters that are valid on Linux but handled badly on macOS: ':' is the path
+// separator of HFS+ (and shown as '/' in Finder), '\' is treated as path separator by
+// some zip tools.
+const PROBLEMATIC_CHARACTERS: &[char] = &[':', '\\'];
+
+// Maximum length of file name on HFS+ and APFS
+const MAX_NAME_LENGTH: usize = 255;
+
+//
+// Checks that all file names of a bundle survive a trip to a Mac. Bundles are often
+// assembled on case-sensitive filesystems, while macOS filesystems are usually case
+// and normalization insensitive. Names are collected from the inputs before anything
+// is copied (copying colliding names to case-insensitive volume would silently
+// overwrite files). Reports:
+//   - names in same folder that differ only by case or Unicode normalization
+//   - names that are not valid UTF-8
+//   - names with control or problematic characters, leading or trailing whitespace
+//     and names that are too long
+//
+#[derive(Default)]
+pub struct BundleNames {
+    // Names keyed by folder within bundle; Inputs copied into same folder (i.e.
+    // resources merged into en.lproj) are checked together
+    folders: BTreeMap<PathBuf, BTreeSet<OsString>>,
+    visited: HashSet<PathBuf>,
+}
+
+impl BundleNames {
+    // Adds content of source folder as content of destination folder in bundle
+    pub fn add_dir(&mut self, source: &Path, destination: &Path) -> ToolResult<()> {
+        let root = source
+            .canonicalize()
+            .wrap_error(FileOperation::Canonicalize, || source.into())?;
+        self.folders.entry(destination.into()).or_default();
+        self.add_content(&root, source, destination)
+    }
+
+    // Adds file or folder with its content at destination path in bundle
+    pub fn add(&mut self, source: &Path, destination: &Path) -> ToolResult<()> {
+        let parent = destination.parent().unwrap_or_else(|| Path::new(""));
+        if let Some(name) = destination.file_name() {
+            self.folders
+                .entry(parent.into())
+                .or_default()
+                .insert(name.into());
+        }
+        if source.is_dir() {
+            self.add_dir(source, destination)?;
+        }
+        Ok(())
+    }
+
+    // Symlinks to folders within the input are preserved when copying, symlinks
+    // pointing outside of it are replaced by content of the target
+    fn add_content(&mut self, root: &Path, dir: &Path, destination: &Path) -> ToolResult<()> {
+        let resolved = dir
+            .canonicalize()
+            .wrap_error(FileOperation::Canonicalize, || dir.into())?;
+        if !self.visited.insert(resolved) {
+            return Ok(());
+        }
+        let mut entries = Vec::new();
+        for entry in dir
+            .read_dir()
+            .wrap_error(FileOperation::ReadDir, || dir.into())?
+        {
+            let entry = entry.wrap_error(FileOperation::Read, || dir.into())?;
+            entries.push(entry.path());
+        }
+        entries.sort();
+        for path in entries {
+            let name = path.file_name().unwrap();
+            self.folders
+                .entry(destination.into())
+                .or_default()
+                .insert(name.into());
+            let meta = path
+                .symlink_metadata()
+                .wrap_error(FileOperation::Metadata, || path.clone())?;
+            let descend = if meta.is_symlink() {
+                path.is_dir()
+                    && !path
+                        .canonicalize()
+                        .wrap_error(FileOperation::Canonicalize, || path.clone())?
+                        .starts_with(root)
+            } else {
+                meta.is_dir()
+            };
+            if descend {
+                self.add_content(root, &path, &destination.join(name))?;
+            }
+        }
+        debug!("{:?}: collected file names", dir);
+        Ok(())
+    }
+
+    pub fn check(&self) -> ToolResult<()> {
+        let mut problems = Vec::new();
+        for (folder, names) in &self.folders {
+            check_names(folder, names, &mut problems);
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ToolError::OtherError(format!(
+                "Bundle contains file names that are not portable to macOS:\n  {}",
+                problems.join("\n  ")
+            )))
+        }
+    }
+}
+
+// Checks names of a single input bundle copied as is
+pub fn check_portability(bundle_path: &Path) -> ToolResult<()> {
+    let mut names = BundleNames::default();
+    names.add_dir(bundle_path, Path::new(""))?;
+    names.check()
+}
+
+fn check_names(folder: &Path, names: &BTreeSet<OsString>, problems: &mut Vec<String>) {
+    // Names in this folder keyed by case and normalization insensitive form
+    let mut folded = BTreeMap::<String, Vec<&str>>::new();
+    for name in names {
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => {
+                problems.push(format!("{:?} is not valid UTF-8", folder.join(name)));
+                continue;
+            }
+        };
+        if let Some(problem) = name_problem(name) {
+            problems.push(format!("{:?} {}", folder.join(name), problem));
+        }
+        let key = name.nfd().collect::<String>().to_lowercase();
+        folded.entry(key).or_default().push(name);
+    }
+    for names in folded.values().filter(|names| names.len() > 1) {
+        let kind = if names.iter().all(|n| n.nfc().eq(names[0].nfc())) {
+            "differ only by Unicode normalization"
+        } else {
+            "collide on case-insensitive filesystem"
+        };
+        problems.push(format!(
+            "{} in {:?} {}",
+            names
+                .iter()
+                .map(|n| format!("{:?}", n))
+                .collect::<Vec<_>>()
+                .join(", "),
+            folder,
+            kind
+        ));
+    }
+}
+
+fn name_problem(name: &str) -> Option<String> {
+    if let Some(c) = name.chars().find(|c| c.is_control()) {
+        Some(format!("contains control character {:?}", c))
+    } else if let Some(c) = name.chars().find(|c| PROBLEMATIC_CHARACTERS.contains(c)) {
+        Some(format!("contains '{}'", c))
+    } else if name.starts_with(char::is_whitespace) || name.ends_with(char::is_whitespace) {
+        Some("starts or ends with whitespace".into())
+    } else if name.encode_utf16().count() > MAX_NAME_LENGTH {
+        Some(format!("is longer than {} characters", MAX_NAME_LENGTH))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsStr, fs, os::unix::ffi::OsStrExt};
+
+    use super::*;
+    use crate::utils::temp_dir;
+
+    // Problems reported for given names in bundle root
+    fn problems(names: &[&OsStr]) -> Vec<String> {
+        let names = names.iter().map(OsString::from).collect();
+        let mut problems = Vec::new();
+        check_names(Path::new("Contents"), &names, &mut problems);
+        problems
+    }
+
+    #[test]
+    fn reports_case_collisions() {
+        let res = problems(&["Info.plist".as_ref(), "info.plist".as_ref()]);
+        assert_eq!(res.len(), 1);
+        assert!(res[0].contains("collide on case-insensitive filesystem"));
+        assert!(problems(&["Info.plist".as_ref(), "Info.plist.bak".as_ref()]).is_empty());
+    }
+
+    #[test]
+    fn reports_normalization_collisions() {
+        // "é" precomposed (NFC) and as "e" with combining accent (NFD)
+        let res = problems(&["caf\u{e9}".as_ref(), "cafe\u{301}".as_ref()]);
+        assert_eq!(res.len(), 1);
+        assert!(res[0].contains("differ only by Unicode normalization"));
+        // Differs by both case and normalization
+        let res = problems(&["Caf\u{e9}".as_ref(), "cafe\u{301}".as_ref()]);
+        assert!(res[0].contains("collide on case-insensitive filesystem"));
+    }
+
+    #[test]
+    fn reports_invalid_utf8() {
+        let res = problems(&[OsStr::from_bytes(b"caf\xe9")]);
+        assert_eq!(res, vec![r#""Contents/caf\xE9" is not valid UTF-8"#]);
+    }
+
+    #[test]
+    fn reports_problematic_names() {
+        assert_eq!(
+            name_problem("a\tb").unwrap(),
+            "contains control character '\\t'"
+        );
+        assert_eq!(name_problem("a:b").unwrap(), "contains ':'");
+        assert_eq!(name_problem("a\\b").unwrap(), "contains '\\'");
+        assert!(name_problem(" a").is_some());
+        assert!(name_problem("a ").is_some());
+        assert!(name_problem(&"a".repeat(MAX_NAME_LENGTH)).is_none());
+        assert!(name_problem(&"a".repeat(MAX_NAME_LENGTH + 1)).is_some());
+        // Length is measured in UTF-16 units, as on HFS+
+        assert!(name_problem(&"\u{1f600}".repeat(MAX_NAME_LENGTH / 2 + 1)).is_some());
+        assert!(name_problem("Résumé 2.txt").is_none());
+    }
+
+    #[test]
+    fn checks_inputs_merged_into_same_folder() {
+        let root = temp_dir().unwrap();
+        let bundle = root.join("A.app");
+        let lproj = bundle.join("Contents/Resources/en.lproj");
+        fs::create_dir_all(&lproj).unwrap();
+        fs::write(lproj.join("Main.strings"), b"").unwrap();
+        let resource = root.join("main.strings");
+        fs::write(&resource, b"").unwrap();
+
+        let mut names = BundleNames::default();
+        names.add_dir(&bundle, Path::new("")).unwrap();
+        assert!(names.check().is_ok());
+        names
+            .add(
+                &resource,
+                Path::new("Contents/Resources/en.lproj/main.strings"),
+            )
+            .unwrap();
+        let err = names.check().unwrap_err().to_string();
+        assert!(err.contains(r#""Main.strings", "main.strings" in "Contents/Resources/en.lproj""#));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn follows_symlinks_out_of_input() {
+        let root = temp_dir().unwrap();
+        let bundle = root.join("A.app");
+        let external = root.join("external");
+        fs::create_dir_all(bundle.join("Contents/Versions/A")).unwrap();
+        fs::create_dir_all(&external).unwrap();
+        fs::write(external.join("a"), b"").unwrap();
+        fs::write(external.join("A"), b"").unwrap();
+        std::os::unix::fs::symlink(&external, bundle.join("Contents/Data")).unwrap();
+        // Symlink within bundle is preserved and its target checked only once
+        std::os::unix::fs::symlink("Versions/A", bundle.join("Contents/Current")).unwrap();
+
+        let mut names = BundleNames::default();
+        names.add_dir(&bundle, Path::new("")).unwrap();
+        assert!(!names.folders.contains_key(Path::new("Contents/Current")));
+        let err = names.check().unwrap_err().to_string();
+        assert!(err.contains(r#""A", "a" in "Contents/Data""#));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use log::debug;
+
+use crate::{
+    config::{BundleConfig, ResourceConfig},
+    error::{FileOperation, IOResultExt, ToolError, ToolResult},
+    utils::{expand_glob, Copier},
+};
+
+#[derive(clap::Parser)]
+pub struct ResourceOptions {
+    /// Additional resource to copy into Contents/Resources in form of
+    /// <glob>[=<folder>], i.e. "assets/fonts/*.ttf=Fonts". Matched folders (such as
+    /// .lproj) are copied with their content; With "**" the structure below the
+    /// literal part of the glob is preserved. Can be specified multiple times.
+    #[clap(long, parse(from_str = parse_resource))]
+    resource: Vec<ResourceConfig>,
+}
+
+// "<glob>[=<folder>]" -> resource
+fn parse_resource(value: &str) -> ResourceConfig {
+    match value.split_once('=') {
+        Some((source, destination)) => ResourceConfig {
+            source: source.into(),
+            destination: Some(destination.into()),
+        },
+        None => ResourceConfig {
+            source: value.into(),
+            destination: None,
+        },
+    }
+}
+
+impl ResourceOptions {
+    pub fn apply_config(&mut self, config: &BundleConfig) {
+        if self.resource.is_empty() {
+            self.resource = config.resources.clone();
+        }
+    }
+}
+
+//
+// Copies declared resources into Contents/Resources of the bundle. Resources are
+// copied after the source bundle, so existing folders (i.e. en.lproj) are merged,
+// but a file that already exists in bundle is an error.
+//
+pub fn copy_resources(
+    options: &ResourceOptions,
+    bundle_path: &Path,
+    copier: &mut Copier,
+) -> ToolResult<()> {
+    for (source, target) in resource_targets(options)? {
+        let target = bundle_path.join(target);
+        debug!("{:?}: copy resource to {:?}", source, target);
+        copy_resource(&source, &target, bundle_path, copier)?;
+    }
+    Ok(())
+}
+
+// Files and folders matched by declared resources with their destination relative
+// to the bundle
+pub fn resource_targets(options: &ResourceOptions) -> ToolResult<Vec<(PathBuf, PathBuf)>> {
+    let resources = Path::new("Contents").join("Resources");
+    let mut targets = Vec::new();
+    for resource in &options.resource {
+        let destination = match &resource.destination {
+            Some(destination) => {
+                // Leading separator is allowed; Folder is always relative to Resources
+                let destination: PathBuf = destination
+                    .components()
+                    .filter(|c| *c != Component::RootDir)
+                    .collect();
+                if !destination
+                    .components()
+                    .all(|c| matches!(c, Component::Normal(_)))
+                {
+                    return Err(ToolError::OtherError(format!(
+                        "Invalid destination {:?} of resource {:?}; Folder must be within Contents/Resources",
+                        resource.destination.as_ref().unwrap(),
+                        resource.source
+                    )));
+                }
+                resources.join(destination)
+            }
+            None => resources.clone(),
+        };
+        let (base, matches) = expand_glob(&resource.source)?;
+        if matches.is_empty() {
+            return Err(ToolError::OtherError(format!(
+                "Resource {:?} doesn't match any file",
+                resource.source
+            )));
+        }
+        for source in matches {
+            let target = destination.join(source.strip_prefix(&base).unwrap());
+            targets.push((source, target));
+        }
+    }
+    Ok(targets)
+}
+
+fn copy_resource(
+    source: &Path,
+    target: &Path,
+    bundle_path: &Path,
+    copier: &mut Copier,
+) -> ToolResult<()> {
+    let resolved = source
+        .canonicalize()
+        .wrap_error(FileOperation::Canonicalize, || source.into())?;
+    if resolved.is_dir() {
+        if target.exists() && !target.is_dir() {
+            return Err(collision(source, target, bundle_path));
+        }
+        // Folders already in bundle (i.e. en.lproj) keep their own metadata
+        let created = !target.exists();
+        fs::create_dir_all(target).wrap_error(FileOperation::MkDir, || target.into())?;
+        let mut entries = Vec::new();
+        for entry in resolved
+            .read_dir()
+            .wrap_error(FileOperation::ReadDir, || source.into())?
+        {
+            let entry = entry.wrap_error(FileOperation::Read, || source.into())?;
+            entries.push(entry.file_name());
+        }
+        entries.sort();
+        for name in entries {
+            copy_resource(
+                &source.join(&name),
+                &target.join(&name),
+                bundle_path,
+                copier,
+            )?;
+        }
+        if created {
+            copier
+                .copy_metadata(&resolved, target)
+                .wrap_error_with_src(FileOperation::Copy, || target.into(), || source.into())?;
+        }
+        Ok(())
+    } else {
+        if target.symlink_metadata().is_ok() {
+            return Err(collision(source, target, bundle_path));
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).wrap_error(FileOperation::MkDir, || parent.into())?;
+        }
+        copier.copy_file(&resolved, target).wrap_error_with_src(
+            FileOperation::Copy,
+            || target.into(),
+            || source.into(),
+        )?;
+        Ok(())
+    }
+}
+
+fn collision(source: &Path, target: &Path, bundle_path: &Path) -> ToolError {
+    ToolError::OtherError(format!(
+        "Resource {:?} collides with {:?} already in bundle",
+        source,
+        target.strip_prefix(bundle_path).unwrap_or(target)
+    ))
+}
@@ -0,0 +1,370 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File},
+    io,
+    os::unix::fs::MetadataExt,
+    path::{Component, Path, PathBuf},
+};
+
+use log::debug;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::required,
+    error::{FileOperation, IOResultExt, ToolError, ToolResult},
+};
+
+use super::{
+    macho::MachFile,
+    utils::{classify_binary, format_size, write_stdout},
+};
+
+// Extensions of nested bundles; Their content is sealed by their own signature.
+const BUNDLE_EXTENSIONS: &[&str] = &["app", "appex", "bundle", "framework", "plugin", "xpc"];
+
+#[derive(clap::Parser)]
+pub struct Options {
+    /// Path to bundle to analyze
+    bundle_path: Option<PathBuf>,
+
+    /// Number of largest files to list
+    #[clap(long, default_value = "20")]
+    top: usize,
+
+    /// Replace duplicate files in Contents/Resources (outside of nested bundles) with
+    /// relative symlinks to the first copy. Must be done before code-signing; Signed
+    /// bundles are refused.
+    #[clap(long)]
+    dedup: bool,
+
+    /// Print report as JSON
+    #[clap(long)]
+    json: bool,
+}
+
+pub struct Size {
+    options: Options,
+}
+
+struct FileEntry {
+    path: PathBuf,
+    size: u64,
+    is_binary: bool,
+    // (device, inode); Hardlinks share it and their content is stored once
+    inode: (u64, u64),
+}
+
+#[derive(Serialize)]
+struct SizeReport {
+    total: u64,
+    largest: Vec<FileSize>,
+    frameworks: Vec<FileSize>,
+    // Sum of Mach-O slice sizes per architecture
+    archs: BTreeMap<String, u64>,
+    duplicates: Vec<Duplicates>,
+    // Bytes saved by replacing duplicates with symlinks (only with --dedup)
+    saved: u64,
+}
+
+#[derive(Serialize)]
+struct FileSize {
+    path: PathBuf,
+    size: u64,
+}
+
+#[derive(Serialize)]
+struct Duplicates {
+    sha256: String,
+    size: u64,
+    paths: Vec<PathBuf>,
+}
+
+impl Size {
+    pub fn new(options: Options) -> Self {
+        Self { options }
+    }
+
+    pub fn perform(self) -> ToolResult<()> {
+        let bundle_path = required(&self.options.bundle_path, "bundle path")?;
+        if !bundle_path.is_dir() {
+            return Err(ToolError::OtherError(format!(
+                "Bundle {:?} does not exist",
+                bundle_path
+            )));
+        }
+        if self.options.dedup && bundle_path.join("Contents").join("_CodeSignature").exists() {
+            return Err(ToolError::OtherError(format!(
+                "Bundle {:?} is signed; Replacing files with symlinks would break its signature, so duplicates must be removed before code-signing",
+                bundle_path
+            )));
+        }
+        let mut files = Vec::new();
+        collect_files(&bundle_path, &bundle_path, &mut files)?;
+
+        let mut largest: Vec<_> = files
+            .iter()
+            .map(|f| FileSize {
+                path: f.path.clone(),
+                size: f.size,
+            })
+            .collect();
+        largest.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+        largest.truncate(self.options.top);
+
+        let mut report = SizeReport {
+            total: unique_inodes(&files).map(|f| f.size).sum(),
+            largest,
+            frameworks: framework_sizes(&files),
+            archs: arch_sizes(&bundle_path, &files)?,
+            duplicates: find_duplicates(&bundle_path, &files)?,
+            saved: 0,
+        };
+        if self.options.dedup {
+            report.saved = dedup(&bundle_path, &report.duplicates, &files)?;
+        }
+
+        let out = if self.options.json {
+            let mut json = serde_json::to_string_pretty(&report).map_err(|e| {
+                ToolError::OtherError(format!("Failed to serialize size report: {}", e))
+            })?;
+            json.push('\n');
+            json
+        } else {
+            format_report(&report, self.options.dedup)
+        };
+        write_stdout(&out)
+    }
+}
+
+// Collects regular files (relative to bundle root) recursively; Symlinks are skipped.
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<FileEntry>) -> ToolResult<()> {
+    let mut entries = Vec::new();
+    for entry in dir
+        .read_dir()
+        .wrap_error(FileOperation::ReadDir, || dir.into())?
+    {
+        let entry = entry.wrap_error(FileOperation::Read, || dir.into())?;
+        entries.push(entry.path());
+    }
+    entries.sort();
+    for path in entries {
+        let meta = path
+            .symlink_metadata()
+            .wrap_error(FileOperation::Metadata, || path.clone())?;
+        if meta.is_dir() {
+            collect_files(root, &path, files)?;
+        } else if meta.is_file() {
+            files.push(FileEntry {
+                is_binary: classify_binary(&path)?.is_some(),
+                path: path.strip_prefix(root).unwrap().into(),
+                size: meta.len(),
+                inode: (meta.dev(), meta.ino()),
+            });
+        }
+    }
+    Ok(())
+}
+
+// First entry for each inode; Other entries are hardlinks to it.
+fn unique_inodes(files: &[FileEntry]) -> impl Iterator<Item = &FileEntry> {
+    let mut seen = HashSet::new();
+    files.iter().filter(move |f| seen.insert(f.inode))
+}
+
+// Totals of all (possibly nested) frameworks, largest first
+fn framework_sizes(files: &[FileEntry]) -> Vec<FileSize> {
+    let mut frameworks = BTreeMap::<PathBuf, u64>::new();
+    for file in unique_inodes(files) {
+        let mut prefix = PathBuf::new();
+        for component in file.path.components() {
+            prefix.push(component);
+            if prefix
+                .extension()
+                .map(|e| e == "framework")
+                .unwrap_or(false)
+            {
+                *frameworks.entry(prefix.clone()).or_default() += file.size;
+            }
+        }
+    }
+    let mut res: Vec<_> = frameworks
+        .into_iter()
+        .map(|(path, size)| FileSize { path, size })
+        .collect();
+    res.sort_by_key(|f| std::cmp::Reverse(f.size));
+    res
+}
+
+fn arch_sizes(bundle_path: &Path, files: &[FileEntry]) -> ToolResult<BTreeMap<String, u64>> {
+    let mut res = BTreeMap::new();
+    for file in unique_inodes(files).filter(|f| f.is_binary) {
+        let file = match MachFile::read(&bundle_path.join(&file.path))? {
+            Some(file) => file,
+            None => continue,
+        };
+        for (arch, slice) in file.archs().into_iter().zip(file.raw_slices()) {
+            *res.entry(arch).or_default() += slice.len() as u64;
+        }
+    }
+    Ok(res)
+}
+
+// Groups files with identical content; Only files with matching size are hashed.
+// Hardlinks are not duplicates, so each inode is listed once (by its first path).
+fn find_duplicates(bundle_path: &Path, files: &[FileEntry]) -> ToolResult<Vec<Duplicates>> {
+    let mut by_size = BTreeMap::<u64, Vec<&FileEntry>>::new();
+    for file in unique_inodes(files).filter(|f| f.size > 0) {
+        by_size.entry(file.size).or_default().push(file);
+    }
+    let mut res = Vec::new();
+    for (size, candidates) in by_size {
+        if candidates.len() < 2 {
+            continue;
+        }
+        let mut by_hash = BTreeMap::<String, Vec<PathBuf>>::new();
+        for file in candidates {
+            let hash = sha256(&bundle_path.join(&file.path))?;
+            by_hash.entry(hash).or_default().push(file.path.clone());
+        }
+        for (sha256, paths) in by_hash {
+            if paths.len() > 1 {
+                res.push(Duplicates {
+                    sha256,
+                    size,
+                    paths,
+                });
+            }
+        }
+    }
+    res.sort_by(|a, b| {
+        let wasted = |d: &Duplicates| d.size * (d.paths.len() as u64 - 1);
+        wasted(b).cmp(&wasted(a))
+    });
+    Ok(res)
+}
+
+fn sha256(path: &Path) -> ToolResult<String> {
+    let mut file = File::open(path).wrap_error(FileOperation::Open, || path.into())?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher).wrap_error(FileOperation::Read, || path.into())?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+//
+// Files can only be replaced with symlinks where it doesn't affect code signature:
+// Contents/Resources of the bundle itself, excluding nested bundles (which are
+// sealed separately) and Mach-O binaries (which are signed individually).
+//
+fn can_dedup(file: &FileEntry) -> bool {
+    let mut components = file.path.components();
+    let in_resources = components.next() == Some(Component::Normal("Contents".as_ref()))
+        && components.next() == Some(Component::Normal("Resources".as_ref()));
+    let in_nested_bundle = file.path.ancestors().skip(1).any(|p| {
+        p.extension()
+            .map(|e| BUNDLE_EXTENSIONS.iter().any(|b| e == *b))
+            .unwrap_or(false)
+    });
+    in_resources && !in_nested_bundle && !file.is_binary
+}
+
+//
+// Replaces duplicates with relative symlinks to the first eligible copy; Returns
+// number of bytes saved. Content of a copy is only freed when all of its hardlinks
+// are replaced, so copies with hardlinks that can't be replaced are kept as is.
+//
+fn dedup(bundle_path: &Path, duplicates: &[Duplicates], files: &[FileEntry]) -> ToolResult<u64> {
+    let mut links = HashMap::<(u64, u64), Vec<&FileEntry>>::new();
+    for file in files {
+        links.entry(file.inode).or_default().push(file);
+    }
+    let by_path: HashMap<_, _> = files.iter().map(|f| (&f.path, f.inode)).collect();
+    let mut saved = 0;
+    for duplicate in duplicates {
+        // All paths of each copy
+        let copies: Vec<&Vec<&FileEntry>> = duplicate
+            .paths
+            .iter()
+            .map(|p| &links[&by_path[p]])
+            .collect();
+        let original = match copies.iter().flat_map(|c| c.iter()).find(|f| can_dedup(f)) {
+            Some(original) => &original.path,
+            None => continue,
+        };
+        for copy in copies {
+            if copy.iter().any(|f| &f.path == original) || !copy.iter().all(|f| can_dedup(f)) {
+                continue;
+            }
+            for file in copy {
+                let link = bundle_path.join(&file.path);
+                let target = pathdiff::diff_paths(original, file.path.parent().unwrap()).unwrap();
+                debug!("Replacing {:?} with symlink to {:?}", link, target);
+                fs::remove_file(&link).wrap_error(FileOperation::Remove, || link.clone())?;
+                std::os::unix::fs::symlink(&target, &link)
+                    .wrap_error(FileOperation::SymLink, || link.clone())?;
+            }
+            saved += duplicate.size;
+        }
+    }
+    Ok(saved)
+}
+
+fn format_report(report: &SizeReport, dedup: bool) -> String {
+    let mut out = format!("Total size: {}\n", format_size(report.total));
+    out.push_str("\nLargest files:\n");
+    for file in &report.largest {
+        out.push_str(&format!(
+            "  {:>10}  {}\n",
+            format_size(file.size),
+            file.path.display()
+        ));
+    }
+    if !report.frameworks.is_empty() {
+        out.push_str("\nFrameworks:\n");
+        for framework in &report.frameworks {
+            out.push_str(&format!(
+                "  {:>10}  {}\n",
+                format_size(framework.size),
+                framework.path.display()
+            ));
+        }
+    }
+    if !report.archs.is_empty() {
+        out.push_str("\nArchitectures (Mach-O slices):\n");
+        for (arch, size) in &report.archs {
+            out.push_str(&format!("  {:>10}  {}\n", format_size(*size), arch));
+        }
+    }
+    if !report.duplicates.is_empty() {
+        let wasted: u64 = report
+            .duplicates
+            .iter()
+            .map(|d| d.size * (d.paths.len() as u64 - 1))
+            .sum();
+        out.push_str(&format!(
+            "\nDuplicates ({} in redundant copies):\n",
+            format_size(wasted)
+        ));
+        for duplicate in &report.duplicates {
+            out.push_str(&format!(
+                "  {} x {}\n",
+                duplicate.paths.len(),
+                format_size(duplicate.size)
+            ));
+            for path in &duplicate.paths {
+                out.push_str(&format!("      {}\n", path.display()));
+            }
+        }
+    }
+    if dedup {
+        out.push_str(&format!(
+            "\nReplaced duplicates with symlinks, saved {}\n",
+            format_size(report.saved)
+        ));
+    }
+    out
+}
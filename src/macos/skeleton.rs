@@ -0,0 +1,190 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+
+use crate::{
+    config::{apply, BundleConfig},
+    error::{FileOperation, IOResultExt, PlistResultExt, ToolError, ToolResult},
+};
+
+use super::{info_plist::cargo_bundle_version, macho::MachFile};
+
+#[derive(clap::Parser)]
+pub struct SkeletonOptions {
+    /// Treat source-path as a bare executable and generate the app bundle around it
+    #[clap(long)]
+    from_executable: bool,
+
+    /// Name of generated bundle (without .app extension). Defaults to executable name
+    #[clap(long)]
+    bundle_name: Option<String>,
+
+    /// Icon file (.icns) placed in Contents/Resources of generated bundle
+    #[clap(long)]
+    icon: Option<PathBuf>,
+}
+
+impl SkeletonOptions {
+    pub fn apply_config(&mut self, config: &BundleConfig) {
+        apply(&mut self.bundle_name, &config.bundle_name);
+        apply(&mut self.icon, &config.icon);
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.from_executable
+    }
+}
+
+//
+// Generates .app bundle structure for a bare executable:
+//
+// <Name>.app/Contents/Info.plist
+// <Name>.app/Contents/MacOS/<executable>
+// <Name>.app/Contents/Resources/<icon>
+//
+// Executable and icon are symlinked to their original location. When the skeleton
+// is used as source for SelfContained, the symlinks point out of bundle and are
+// resolved, so that dependencies are still looked up relative to original executable.
+//
+// Version defaults to package version from Cargo.toml and minimum system version to
+// the deployment target of the executable; Both (and other Info.plist keys) can be
+// overridden later by the Info.plist step.
+//
+pub fn create_skeleton(
+    options: &SkeletonOptions,
+    identifier: Option<&str>,
+    executable: &Path,
+    parent_dir: &Path,
+) -> ToolResult<PathBuf> {
+    if !executable.is_file() {
+        return Err(ToolError::OtherError(format!(
+            "Executable {:?} is not a valid file.",
+            executable
+        )));
+    }
+    let identifier = identifier.ok_or_else(|| {
+        ToolError::OtherError("Bundle identifier is required for bare executable.".into())
+    })?;
+    let executable = executable
+        .canonicalize()
+        .wrap_error(FileOperation::Canonicalize, || executable.into())?;
+    let executable_name = executable
+        .file_name()
+        .unwrap()
+        .to_string_lossy()
+        .to_string();
+    let bundle_name = options
+        .bundle_name
+        .clone()
+        .unwrap_or_else(|| executable_name.clone());
+    let version = cargo_bundle_version()?;
+    // Lowest deployment target of all slices
+    let minimum_system_version = MachFile::read(&executable)?
+        .and_then(|file| file.slices().filter_map(|s| s.minimum_os_version()).min());
+
+    let bundle_path = parent_dir.join(format!("{}.app", bundle_name));
+    debug!("Creating bundle skeleton {:?}", bundle_path);
+
+    let contents = bundle_path.join("Contents");
+    let macos = contents.join("MacOS");
+    let resources = contents.join("Resources");
+    for dir in [&macos, &resources] {
+        fs::create_dir_all(dir).wrap_error(FileOperation::MkDir, || dir.into())?;
+    }
+
+    let executable_link = macos.join(&executable_name);
+    std::os::unix::fs::symlink(&executable, &executable_link)
+        .wrap_error(FileOperation::SymLink, || executable_link.clone())?;
+
+    let mut info = plist::Dictionary::new();
+    info.insert("CFBundleDevelopmentRegion".into(), "en".into());
+    info.insert("CFBundleExecutable".into(), executable_name.into());
+    info.insert("CFBundleIdentifier".into(), identifier.into());
+    info.insert("CFBundleInfoDictionaryVersion".into(), "6.0".into());
+    info.insert("CFBundleName".into(), bundle_name.into());
+    info.insert("CFBundlePackageType".into(), "APPL".into());
+    if let Some(version) = version {
+        info.insert("CFBundleShortVersionString".into(), version.clone().into());
+        info.insert("CFBundleVersion".into(), version.into());
+    }
+    if let Some(minimum_system_version) = minimum_system_version {
+        info.insert(
+            "LSMinimumSystemVersion".into(),
+            minimum_system_version.to_string().into(),
+        );
+    }
+    info.insert("NSHighResolutionCapable".into(), true.into());
+    info.insert("NSPrincipalClass".into(), "NSApplication".into());
+
+    if let Some(icon) = &options.icon {
+        let icon = icon
+            .canonicalize()
+            .wrap_error(FileOperation::Canonicalize, || icon.into())?;
+        let icon_name = icon.file_name().unwrap();
+        let icon_link = resources.join(icon_name);
+        std::os::unix::fs::symlink(&icon, &icon_link)
+            .wrap_error(FileOperation::SymLink, || icon_link.clone())?;
+        info.insert(
+            "CFBundleIconFile".into(),
+            icon_name.to_string_lossy().to_string().into(),
+        );
+    }
+
+    let info_plist = contents.join("Info.plist");
+    plist::Value::Dictionary(info)
+        .to_file_xml(&info_plist)
+        .wrap_error(|| Some(info_plist.clone()))?;
+
+    Ok(bundle_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        macos::macho::{test_fixtures::*, Version, MH_EXECUTE},
+        utils::temp_dir,
+    };
+
+    #[test]
+    fn creates_skeleton() {
+        let dir = temp_dir().unwrap();
+        let executable = dir.join("tool");
+        let data = macho(
+            CPU_TYPE_ARM64,
+            MH_EXECUTE,
+            &[build_version(Version::new(10, 15, 0))],
+        );
+        fs::write(&executable, data).unwrap();
+        let options = SkeletonOptions {
+            from_executable: true,
+            bundle_name: Some("Tool".into()),
+            icon: None,
+        };
+        let bundle_path =
+            create_skeleton(&options, Some("com.example.tool"), &executable, &dir).unwrap();
+        assert_eq!(bundle_path, dir.join("Tool.app"));
+        assert_eq!(
+            bundle_path.join("Contents/MacOS/tool").read_link().unwrap(),
+            executable.canonicalize().unwrap()
+        );
+
+        let info = plist::Value::from_file(bundle_path.join("Contents/Info.plist")).unwrap();
+        let info = info.as_dictionary().unwrap();
+        let value = |key: &str| info.get(key).and_then(|v| v.as_string());
+        assert_eq!(value("CFBundleExecutable"), Some("tool"));
+        assert_eq!(value("CFBundleIdentifier"), Some("com.example.tool"));
+        assert_eq!(value("LSMinimumSystemVersion"), Some("10.15"));
+        // Tests run in the directory of this crate
+        assert_eq!(
+            value("CFBundleShortVersionString"),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
+        assert_eq!(value("CFBundleVersion"), Some(env!("CARGO_PKG_VERSION")));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
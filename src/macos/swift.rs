@@ -0,0 +1,222 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use log::debug;
+
+use crate::{
+    config::{apply, apply_flag, BundleConfig},
+    error::{ToolError, ToolResult},
+    utils::run_command,
+};
+
+use super::{
+    bundle::ModulePath,
+    macho::{MachFile, Version},
+    utils::info_plist_string,
+};
+
+// First macOS version that ships given Swift runtime library. Libraries not listed
+// here are part of the ABI stable runtime shipped since macOS 10.14.4.
+const SWIFT_RUNTIME_AVAILABILITY: &[(&str, (u32, u32, u32))] = &[
+    ("libswift_Concurrency.dylib", (12, 0, 0)),
+    ("libswift_StringProcessing.dylib", (13, 0, 0)),
+    ("libswift_RegexParser.dylib", (13, 0, 0)),
+    ("libswiftObservation.dylib", (14, 0, 0)),
+    ("libswiftSynchronization.dylib", (15, 0, 0)),
+];
+
+const SWIFT_ABI_STABLE_OS: (u32, u32, u32) = (10, 14, 4);
+
+// Folders (relative to toolchain) that contain back-deployment Swift runtime libraries
+const SWIFT_RUNTIME_DIRS: &[&str] = &[
+    "usr/lib/swift-5.0/macosx",
+    "usr/lib/swift-5.5/macosx",
+    "usr/lib/swift/macosx",
+];
+
+#[derive(clap::Parser)]
+pub struct SwiftOptions {
+    /// Bundle Swift runtime libraries that are not part of macOS at the bundle
+    /// deployment target
+    #[clap(long, overrides_with = "no_embed_swift_runtime")]
+    embed_swift_runtime: bool,
+
+    /// Do not bundle Swift runtime libraries (overrides configuration)
+    #[clap(long, overrides_with = "embed_swift_runtime")]
+    no_embed_swift_runtime: bool,
+
+    /// Toolchain used to look up Swift runtime libraries. Defaults to active Xcode toolchain
+    #[clap(long)]
+    swift_toolchain: Option<PathBuf>,
+}
+
+impl SwiftOptions {
+    pub fn apply_config(&mut self, config: &BundleConfig) {
+        apply_flag(
+            &mut self.embed_swift_runtime,
+            self.no_embed_swift_runtime,
+            config.embed_swift_runtime,
+        );
+        apply(&mut self.swift_toolchain, &config.swift_toolchain);
+    }
+}
+
+pub struct SwiftRuntime {
+    deployment_target: Version,
+    search_paths: Vec<PathBuf>,
+}
+
+impl SwiftRuntime {
+    // Returns Swift runtime configuration for given bundle, or None if Swift runtime
+    // embedding is not enabled.
+    pub fn new(options: &SwiftOptions, bundle_path: &Path) -> ToolResult<Option<Self>> {
+        if !options.embed_swift_runtime {
+            return Ok(None);
+        }
+        let deployment_target = deployment_target(bundle_path)?;
+        let toolchain = match &options.swift_toolchain {
+            Some(toolchain) => toolchain.clone(),
+            None => default_toolchain()?,
+        };
+        let search_paths: Vec<_> = SWIFT_RUNTIME_DIRS
+            .iter()
+            .map(|d| toolchain.join(d))
+            .filter(|d| d.is_dir())
+            .collect();
+        if search_paths.is_empty() {
+            return Err(ToolError::OtherError(format!(
+                "No Swift runtime libraries found in toolchain {:?}",
+                toolchain
+            )));
+        }
+        debug!(
+            "Swift runtime: deployment target {}, search paths {:?}",
+            deployment_target, search_paths
+        );
+        Ok(Some(Self {
+            deployment_target,
+            search_paths,
+        }))
+    }
+
+    pub fn search_paths(&self) -> &[PathBuf] {
+        &self.search_paths
+    }
+
+    // Returns whether the Swift runtime library is provided by the OS at deployment target
+    pub fn is_system(&self, path: &ModulePath) -> bool {
+        let name = path.file_name();
+        let (major, minor, patch) = SWIFT_RUNTIME_AVAILABILITY
+            .iter()
+            .find(|(lib, _)| *lib == name)
+            .map(|(_, version)| *version)
+            .unwrap_or(SWIFT_ABI_STABLE_OS);
+        self.deployment_target >= Version::new(major, minor, patch)
+    }
+}
+
+// Deployment target is LSMinimumSystemVersion, or if not specified, minimum OS version
+// of bundle executable.
+fn deployment_target(bundle_path: &Path) -> ToolResult<Version> {
+    let info_plist = bundle_path.join("Contents/Info.plist");
+    if let Some(version) = info_plist_string(&info_plist, "LSMinimumSystemVersion")? {
+        return version.parse();
+    }
+    let executable = info_plist_string(&info_plist, "CFBundleExecutable")?.ok_or_else(|| {
+        ToolError::OtherError(format!("Missing CFBundleExecutable in {:?}", info_plist))
+    })?;
+    let executable = bundle_path.join("Contents/MacOS").join(executable);
+    MachFile::read(&executable)?
+        .and_then(|f| f.slices().filter_map(|s| s.minimum_os_version()).min())
+        .ok_or_else(|| {
+            ToolError::OtherError(format!(
+                "Could not determine deployment target of {:?}",
+                executable
+            ))
+        })
+}
+
+fn default_toolchain() -> ToolResult<PathBuf> {
+    let mut cmd = Command::new("xcrun");
+    cmd.arg("--find").arg("swift");
+    let swift = run_command(cmd, "xcrun")?.join("");
+    // <toolchain>/usr/bin/swift
+    Path::new(swift.trim())
+        .ancestors()
+        .nth(3)
+        .map(|p| p.into())
+        .ok_or_else(|| ToolError::OtherError(format!("Unexpected swift location: {}", swift)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        macos::{
+            info_plist::set_info_plist_value,
+            macho::{test_fixtures::*, MH_EXECUTE},
+        },
+        utils::temp_dir,
+    };
+
+    fn is_system(runtime: &SwiftRuntime, name: &str) -> bool {
+        runtime.is_system(&ModulePath::new(format!("@rpath/{}", name)))
+    }
+
+    #[test]
+    fn uses_bundle_deployment_target() {
+        let dir = temp_dir().unwrap();
+        let toolchain = dir.join("toolchain");
+        fs::create_dir_all(toolchain.join("usr/lib/swift/macosx")).unwrap();
+        let bundle_path = dir.join("App.app");
+        fs::create_dir_all(bundle_path.join("Contents/MacOS")).unwrap();
+        fs::write(
+            bundle_path.join("Contents/MacOS/App"),
+            macho(
+                CPU_TYPE_X86_64,
+                MH_EXECUTE,
+                &[build_version(Version::new(10, 13, 0))],
+            ),
+        )
+        .unwrap();
+        let mut info = plist::Dictionary::new();
+        info.insert("CFBundleExecutable".into(), "App".into());
+        plist::Value::Dictionary(info)
+            .to_file_xml(bundle_path.join("Contents/Info.plist"))
+            .unwrap();
+        let options = SwiftOptions {
+            embed_swift_runtime: true,
+            no_embed_swift_runtime: false,
+            swift_toolchain: Some(toolchain.clone()),
+        };
+
+        // Minimum OS version of executable
+        let runtime = SwiftRuntime::new(&options, &bundle_path).unwrap().unwrap();
+        assert_eq!(
+            runtime.search_paths(),
+            &[toolchain.join("usr/lib/swift/macosx")]
+        );
+        assert!(!is_system(&runtime, "libswiftCore.dylib"));
+
+        // LSMinimumSystemVersion takes precedence
+        set_info_plist_value(&bundle_path, "LSMinimumSystemVersion", "12.0".into()).unwrap();
+        let runtime = SwiftRuntime::new(&options, &bundle_path).unwrap().unwrap();
+        assert!(is_system(&runtime, "libswiftCore.dylib"));
+        assert!(is_system(&runtime, "libswift_Concurrency.dylib"));
+        assert!(!is_system(&runtime, "libswift_StringProcessing.dylib"));
+
+        let disabled = SwiftOptions {
+            embed_swift_runtime: false,
+            ..options
+        };
+        assert!(SwiftRuntime::new(&disabled, &bundle_path)
+            .unwrap()
+            .is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
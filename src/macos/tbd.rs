@@ -0,0 +1,612 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use log::debug;
+
+use crate::error::{FileOperation, IOResultExt, ToolError, ToolResult};
+
+// Single library described by text-based stub
+#[derive(Debug, Default)]
+pub struct Stub {
+    pub install_name: String,
+    // Exported symbols per architecture
+    exports: HashMap<String, HashSet<String>>,
+    pub reexported_libraries: Vec<String>,
+}
+
+impl Stub {
+    // Symbols exported for given architecture, or None if library doesn't support it
+    pub fn symbols(&self, arch: &str) -> Option<&HashSet<String>> {
+        self.exports.get(arch)
+    }
+}
+
+// Text-based stubs from macOS SDK (i.e. MacOSX11.3.sdk), looked up by install name
+pub struct SdkStubs {
+    root: PathBuf,
+    stubs: HashMap<String, Option<Rc<Stub>>>,
+}
+
+impl SdkStubs {
+    pub fn new(root: &Path) -> ToolResult<Self> {
+        if !root.is_dir() {
+            return Err(ToolError::OtherError(format!(
+                "SDK stubs folder {:?} does not exist",
+                root
+            )));
+        }
+        Ok(Self {
+            root: root.into(),
+            stubs: HashMap::new(),
+        })
+    }
+
+    pub fn find(&mut self, install_name: &str) -> ToolResult<Option<Rc<Stub>>> {
+        if let Some(stub) = self.stubs.get(install_name) {
+            return Ok(stub.clone());
+        }
+        let mut res = None;
+        for candidate in self.stub_paths(install_name) {
+            if !candidate.is_file() {
+                continue;
+            }
+            debug!("Loading stub {:?}", candidate);
+            let text = fs::read_to_string(&candidate)
+                .wrap_error(FileOperation::Read, || candidate.clone())?;
+            let stubs = parse(&text).map_err(|e| {
+                ToolError::OtherError(format!("Malformed stub {:?}: {}", candidate, e))
+            })?;
+            // Stub file may contain documents for re-exported libraries as well
+            for stub in stubs {
+                let stub = Rc::new(stub);
+                if res.is_none() {
+                    res = Some(stub.clone());
+                }
+                if stub.install_name == install_name {
+                    res = Some(stub.clone());
+                }
+                self.stubs
+                    .entry(stub.install_name.clone())
+                    .or_insert(Some(stub));
+            }
+            break;
+        }
+        self.stubs.insert(install_name.into(), res.clone());
+        Ok(res)
+    }
+
+    // Stub is next to where the library would be in SDK, with .tbd extension. Swift
+    // runtime referenced through @rpath lives in /usr/lib/swift.
+    fn stub_paths(&self, install_name: &str) -> Vec<PathBuf> {
+        let install_name = match install_name.strip_prefix("@rpath/") {
+            Some(name) if name.starts_with("libswift") => format!("/usr/lib/swift/{}", name),
+            Some(_) => return Vec::new(),
+            None => install_name.into(),
+        };
+        let path = self.root.join(install_name.trim_start_matches('/'));
+        let mut res = Vec::new();
+        if path.extension().map(|e| e == "dylib").unwrap_or(false) {
+            res.push(path.with_extension("tbd"));
+        } else {
+            res.push(PathBuf::from(format!("{}.tbd", path.display())));
+            // Foo.framework/Versions/A/Foo -> Foo.framework/Foo.tbd
+            if let Some(framework) = path
+                .ancestors()
+                .find(|p| p.extension().map(|e| e == "framework").unwrap_or(false))
+            {
+                let name = path.file_name().unwrap().to_string_lossy();
+                res.push(framework.join(format!("{}.tbd", name)));
+            }
+        }
+        res
+    }
+}
+
+// Parses text-based stub file. Supports YAML (tbd-version 1 to 4) and JSON
+// (tbd-version 5) formats.
+pub fn parse(text: &str) -> Result<Vec<Stub>, String> {
+    if text.trim_start().starts_with('{') {
+        parse_json(text)
+    } else {
+        Ok(split_documents(text)
+            .iter()
+            .map(|d| parse_yaml_document(d))
+            .filter(|s| !s.install_name.is_empty())
+            .collect())
+    }
+}
+
+// Splits multi-document file; Each document starts with its "--- !tapi-tbd" header.
+fn split_documents(text: &str) -> Vec<Vec<&str>> {
+    let mut res = Vec::new();
+    let mut current = Vec::new();
+    for line in text.lines() {
+        if (line.starts_with("---") || line.trim_end() == "...") && !current.is_empty() {
+            res.push(std::mem::take(&mut current));
+        }
+        if line.trim_end() != "..." {
+            current.push(line);
+        }
+    }
+    if !current.is_empty() {
+        res.push(current);
+    }
+    res
+}
+
+// Lines of a YAML document as (indentation, key, value); flow sequences spanning
+// multiple lines are joined. List item marker ("- ") is reported as key "-".
+fn yaml_entries(lines: &[&str]) -> Vec<(usize, String, String)> {
+    let mut res = Vec::new();
+    let mut iter = lines.iter();
+    while let Some(line) = iter.next() {
+        let content = line.trim_start();
+        if content.is_empty() || content.starts_with('#') || content.starts_with("---") {
+            continue;
+        }
+        let mut indent = line.len() - content.len();
+        let mut content = content;
+        if let Some(rest) = content.strip_prefix("- ") {
+            res.push((indent, "-".to_string(), String::new()));
+            indent += 2;
+            content = rest.trim_start();
+        }
+        let (key, value) = match content.split_once(':') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        let mut value = value.to_string();
+        if value.starts_with('[') {
+            while !value.contains(']') {
+                match iter.next() {
+                    Some(next) => {
+                        value.push(' ');
+                        value.push_str(next.trim());
+                    }
+                    None => break,
+                }
+            }
+        }
+        res.push((indent, key.to_string(), value));
+    }
+    res
+}
+
+fn flow_sequence(value: &str) -> Vec<String> {
+    value
+        .trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(',')
+        .map(unquote)
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    for quote in &['\'', '"'] {
+        if value.len() >= 2 && value.starts_with(*quote) && value.ends_with(*quote) {
+            return value[1..value.len() - 1].into();
+        }
+    }
+    value.into()
+}
+
+// "arm64-macos" -> "arm64"; Targets for other platforms are ignored.
+fn target_arch(target: &str) -> Option<String> {
+    match target.split_once('-') {
+        Some((arch, "macos")) => Some(arch.into()),
+        Some(_) => None,
+        None => Some(target.into()),
+    }
+}
+
+fn parse_yaml_document(lines: &[&str]) -> Stub {
+    let mut stub = Stub::default();
+    let mut version = match lines.first() {
+        Some(header) if header.contains("tapi-tbd-v3") => 3,
+        Some(header) if header.contains("tapi-tbd-v2") => 2,
+        _ => 1,
+    };
+    let mut section = String::new();
+    let mut document_archs = Vec::<String>::new();
+    let mut item_archs = Vec::<String>::new();
+
+    for (indent, key, value) in yaml_entries(lines) {
+        if indent == 0 {
+            section = key.clone();
+            match key.as_str() {
+                "tbd-version" => version = value.parse().unwrap_or(version),
+                "install-name" => stub.install_name = unquote(&value),
+                "archs" | "targets" => {
+                    document_archs = flow_sequence(&value)
+                        .iter()
+                        .filter_map(|t| target_arch(t))
+                        .collect();
+                    for arch in &document_archs {
+                        stub.exports.entry(arch.clone()).or_default();
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+        match key.as_str() {
+            "-" => item_archs = document_archs.clone(),
+            "archs" | "targets" => {
+                item_archs = flow_sequence(&value)
+                    .iter()
+                    .filter_map(|t| target_arch(t))
+                    .collect();
+            }
+            _ => {}
+        }
+        let values = flow_sequence(&value);
+        let symbols: Vec<String> = match (section.as_str(), key.as_str()) {
+            ("reexported-libraries", "libraries") | ("exports", "re-exports") => {
+                stub.reexported_libraries.extend(values);
+                continue;
+            }
+            ("exports" | "reexports", _) => match key.as_str() {
+                "symbols" | "weak-symbols" | "weak-def-symbols" | "thread-local-symbols" => values,
+                "objc-classes" => values
+                    .iter()
+                    .flat_map(|c| {
+                        // Version 3 stubs prefix class names with underscore
+                        let c = if version == 3 {
+                            c.strip_prefix('_').unwrap_or(c)
+                        } else {
+                            c
+                        };
+                        vec![
+                            format!("_OBJC_CLASS_$_{}", c),
+                            format!("_OBJC_METACLASS_$_{}", c),
+                        ]
+                    })
+                    .collect(),
+                "objc-eh-types" => values
+                    .iter()
+                    .map(|c| format!("_OBJC_EHTYPE_$_{}", c))
+                    .collect(),
+                "objc-ivars" => values
+                    .iter()
+                    .map(|c| format!("_OBJC_IVAR_$_{}", c))
+                    .collect(),
+                _ => continue,
+            },
+            _ => continue,
+        };
+        for arch in &item_archs {
+            stub.exports
+                .entry(arch.clone())
+                .or_default()
+                .extend(symbols.iter().cloned());
+        }
+    }
+    stub
+}
+
+fn parse_json(text: &str) -> Result<Vec<Stub>, String> {
+    let value: serde_json::Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    let mut res = Vec::new();
+    if let Some(main) = value.get("main_library") {
+        res.push(parse_json_library(main));
+    }
+    if let Some(libraries) = value.get("libraries").and_then(|l| l.as_array()) {
+        res.extend(libraries.iter().map(parse_json_library));
+    }
+    Ok(res)
+}
+
+fn json_strings(value: Option<&serde_json::Value>) -> Vec<String> {
+    value
+        .and_then(|v| v.as_array())
+        .map(|a| {
+            a.iter()
+                .filter_map(|v| v.as_str())
+                .map(|s| s.into())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn parse_json_library(library: &serde_json::Value) -> Stub {
+    let mut stub = Stub {
+        install_name: library
+            .get("install_names")
+            .and_then(|n| n.get(0))
+            .and_then(|n| n.get("name"))
+            .and_then(|n| n.as_str())
+            .unwrap_or_default()
+            .into(),
+        ..Default::default()
+    };
+    let document_archs: Vec<String> = library
+        .get("target_info")
+        .and_then(|t| t.as_array())
+        .map(|t| {
+            t.iter()
+                .filter_map(|t| t.get("target").and_then(|t| t.as_str()))
+                .filter_map(target_arch)
+                .collect()
+        })
+        .unwrap_or_default();
+    for arch in &document_archs {
+        stub.exports.entry(arch.clone()).or_default();
+    }
+    for key in &["exported_symbols", "reexported_symbols"] {
+        let sections = library.get(*key).and_then(|s| s.as_array());
+        for section in sections.into_iter().flatten() {
+            let archs = match section.get("targets") {
+                Some(targets) => json_strings(Some(targets))
+                    .iter()
+                    .filter_map(|t| target_arch(t))
+                    .collect(),
+                None => document_archs.clone(),
+            };
+            let mut symbols = Vec::new();
+            for kind in &["data", "text"] {
+                let symbol_set = match section.get(*kind) {
+                    Some(symbol_set) => symbol_set,
+                    None => continue,
+                };
+                for key in &["global", "weak", "thread_local"] {
+                    symbols.extend(json_strings(symbol_set.get(*key)));
+                }
+                for class in json_strings(symbol_set.get("objc_class")) {
+                    symbols.push(format!("_OBJC_CLASS_$_{}", class));
+                    symbols.push(format!("_OBJC_METACLASS_$_{}", class));
+                }
+                for ty in json_strings(symbol_set.get("objc_eh_type")) {
+                    symbols.push(format!("_OBJC_EHTYPE_$_{}", ty));
+                }
+                for ivar in json_strings(symbol_set.get("objc_ivar")) {
+                    symbols.push(format!("_OBJC_IVAR_$_{}", ivar));
+                }
+            }
+            for arch in archs {
+                stub.exports
+                    .entry(arch)
+                    .or_default()
+                    .extend(symbols.iter().cloned());
+            }
+        }
+    }
+    let reexported = library
+        .get("reexported_libraries")
+        .and_then(|r| r.as_array());
+    for entry in reexported.into_iter().flatten() {
+        stub.reexported_libraries.extend(json_strings(
+            entry.get("names").or_else(|| entry.get("libraries")),
+        ));
+    }
+    stub
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbols(stub: &Stub, arch: &str) -> Vec<String> {
+        let mut res: Vec<_> = stub.symbols(arch).unwrap().iter().cloned().collect();
+        res.sort();
+        res
+    }
+
+    #[test]
+    fn parses_yaml_v2() {
+        let text = "--- !tapi-tbd-v2
+archs:           [ i386, x86_64 ]
+platform:        macosx
+install-name:    /usr/lib/libfoo.dylib
+exports:
+  - archs:           [ i386, x86_64 ]
+    re-exports:      [ /usr/lib/libbar.dylib ]
+    symbols:         [ _foo, _foo_common ]
+    objc-classes:    [ Foo ]
+  - archs:           [ x86_64 ]
+    symbols:         [ _foo_64,
+                       'quoted' ]
+    objc-ivars:      [ Foo._ivar ]
+...
+";
+        let stubs = parse(text).unwrap();
+        assert_eq!(stubs.len(), 1);
+        let stub = &stubs[0];
+        assert_eq!(stub.install_name, "/usr/lib/libfoo.dylib");
+        assert_eq!(stub.reexported_libraries, vec!["/usr/lib/libbar.dylib"]);
+        assert_eq!(
+            symbols(stub, "i386"),
+            vec![
+                "_OBJC_CLASS_$_Foo",
+                "_OBJC_METACLASS_$_Foo",
+                "_foo",
+                "_foo_common"
+            ]
+        );
+        assert_eq!(
+            symbols(stub, "x86_64"),
+            vec![
+                "_OBJC_CLASS_$_Foo",
+                "_OBJC_IVAR_$_Foo._ivar",
+                "_OBJC_METACLASS_$_Foo",
+                "_foo",
+                "_foo_64",
+                "_foo_common",
+                "quoted"
+            ]
+        );
+        assert!(stub.symbols("arm64").is_none());
+    }
+
+    #[test]
+    fn parses_yaml_v3_class_names() {
+        let text = "--- !tapi-tbd-v3
+archs:           [ x86_64 ]
+install-name:    /usr/lib/libfoo.dylib
+exports:
+  - archs:           [ x86_64 ]
+    objc-classes:    [ _Foo ]
+    objc-eh-types:   [ Foo ]
+...
+";
+        let stubs = parse(text).unwrap();
+        assert_eq!(
+            symbols(&stubs[0], "x86_64"),
+            vec![
+                "_OBJC_CLASS_$_Foo",
+                "_OBJC_EHTYPE_$_Foo",
+                "_OBJC_METACLASS_$_Foo"
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_yaml_v4_documents() {
+        let text = "--- !tapi-tbd
+tbd-version:     4
+targets:         [ x86_64-macos, arm64-macos, arm64-maccatalyst ]
+install-name:    '/System/Library/Frameworks/Foo.framework/Versions/A/Foo'
+reexported-libraries:
+  - targets:         [ x86_64-macos, arm64-macos ]
+    libraries:       [ '/usr/lib/libbar.dylib' ]
+exports:
+  - targets:         [ x86_64-macos, arm64-macos ]
+    symbols:         [ _Foo ]
+  - targets:         [ arm64-macos ]
+    weak-symbols:    [ _FooWeak ]
+--- !tapi-tbd
+tbd-version:     4
+targets:         [ x86_64-macos, arm64-macos ]
+install-name:    '/usr/lib/libbar.dylib'
+exports:
+  - targets:         [ x86_64-macos, arm64-macos ]
+    thread-local-symbols: [ _bar_tls ]
+...
+";
+        let stubs = parse(text).unwrap();
+        assert_eq!(stubs.len(), 2);
+        let foo = &stubs[0];
+        assert_eq!(
+            foo.install_name,
+            "/System/Library/Frameworks/Foo.framework/Versions/A/Foo"
+        );
+        assert_eq!(foo.reexported_libraries, vec!["/usr/lib/libbar.dylib"]);
+        assert_eq!(symbols(foo, "x86_64"), vec!["_Foo"]);
+        assert_eq!(symbols(foo, "arm64"), vec!["_Foo", "_FooWeak"]);
+        let bar = &stubs[1];
+        assert_eq!(bar.install_name, "/usr/lib/libbar.dylib");
+        assert_eq!(symbols(bar, "arm64"), vec!["_bar_tls"]);
+    }
+
+    #[test]
+    fn parses_json_v5() {
+        let text = r#"{
+  "tapi_tbd_version": 5,
+  "main_library": {
+    "target_info": [
+      { "target": "x86_64-macos", "min_deployment": "10.13" },
+      { "target": "arm64-macos", "min_deployment": "11" },
+      { "target": "arm64-ios" }
+    ],
+    "install_names": [ { "name": "/usr/lib/libfoo.dylib" } ],
+    "reexported_libraries": [ { "names": [ "/usr/lib/libbar.dylib" ] } ],
+    "exported_symbols": [
+      {
+        "data": { "global": [ "_foo_data" ], "objc_class": [ "Foo" ] },
+        "text": { "global": [ "_foo" ], "weak": [ "_foo_weak" ] }
+      },
+      {
+        "targets": [ "arm64-macos" ],
+        "text": { "thread_local": [ "_foo_tls" ], "objc_ivar": [ "Foo._x" ] }
+      }
+    ]
+  },
+  "libraries": [
+    {
+      "target_info": [ { "target": "arm64-macos" } ],
+      "install_names": [ { "name": "/usr/lib/libbar.dylib" } ],
+      "reexported_symbols": [ { "text": { "global": [ "_bar" ] } } ]
+    }
+  ]
+}"#;
+        let stubs = parse(text).unwrap();
+        assert_eq!(stubs.len(), 2);
+        let foo = &stubs[0];
+        assert_eq!(foo.install_name, "/usr/lib/libfoo.dylib");
+        assert_eq!(foo.reexported_libraries, vec!["/usr/lib/libbar.dylib"]);
+        assert_eq!(
+            symbols(foo, "x86_64"),
+            vec![
+                "_OBJC_CLASS_$_Foo",
+                "_OBJC_METACLASS_$_Foo",
+                "_foo",
+                "_foo_data",
+                "_foo_weak"
+            ]
+        );
+        assert_eq!(
+            symbols(foo, "arm64"),
+            vec![
+                "_OBJC_CLASS_$_Foo",
+                "_OBJC_IVAR_$_Foo._x",
+                "_OBJC_METACLASS_$_Foo",
+                "_foo",
+                "_foo_data",
+                "_foo_tls",
+                "_foo_weak"
+            ]
+        );
+        assert_eq!(symbols(&stubs[1], "arm64"), vec!["_bar"]);
+        assert!(parse("{ invalid").is_err());
+    }
+
+    #[test]
+    fn ignores_documents_without_install_name() {
+        assert!(parse("").unwrap().is_empty());
+        assert!(parse("--- !tapi-tbd\ntbd-version: 4\n...\n")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn parses_targets() {
+        assert_eq!(target_arch("arm64-macos"), Some("arm64".into()));
+        assert_eq!(target_arch("arm64e-ios"), None);
+        assert_eq!(target_arch("x86_64"), Some("x86_64".into()));
+        assert_eq!(
+            flow_sequence("[ a, 'b', \"c\" ]"),
+            vec!["a".to_string(), "b".into(), "c".into()]
+        );
+        assert!(flow_sequence("[ ]").is_empty());
+    }
+
+    #[test]
+    fn finds_stub_paths() {
+        let stubs = SdkStubs {
+            root: "/sdk".into(),
+            stubs: HashMap::new(),
+        };
+        assert_eq!(
+            stubs.stub_paths("/usr/lib/libz.1.dylib"),
+            vec![PathBuf::from("/sdk/usr/lib/libz.1.tbd")]
+        );
+        assert_eq!(
+            stubs.stub_paths("/System/Library/Frameworks/Foo.framework/Versions/A/Foo"),
+            vec![
+                PathBuf::from("/sdk/System/Library/Frameworks/Foo.framework/Versions/A/Foo.tbd"),
+                PathBuf::from("/sdk/System/Library/Frameworks/Foo.framework/Foo.tbd"),
+            ]
+        );
+        assert_eq!(
+            stubs.stub_paths("@rpath/libswiftCore.dylib"),
+            vec![PathBuf::from("/sdk/usr/lib/swift/libswiftCore.tbd")]
+        );
+        assert!(stubs.stub_paths("@rpath/libfoo.dylib").is_empty());
+    }
+}
@@ -1,26 +1,61 @@
 use std::{
+    collections::HashSet,
     fs,
     os::unix::prelude::MetadataExt,
     path::{Path, PathBuf},
 };
 
-use crate::error::{FileOperation, IOResultExt, ToolError, ToolResult};
+use crate::{
+    cargo::CargoArgs,
+    config::{apply, apply_flag, required, UniversalConfig},
+    error::{FileOperation, IOResultExt, ToolError, ToolResult},
+    utils::{Copier, CopyArgs, StagingDir},
+};
 
-use super::utils::is_executable_binary;
+use super::{portability::check_portability, utils::classify_binary};
 
 #[derive(clap::Parser)]
 pub struct Options {
-    /// Input paths
-    #[clap(required = true)]
+    /// Input paths. If omitted, bundles are located in cargo target directory using
+    /// --profile and --target.
     paths_in: Vec<PathBuf>,
 
-    /// Output path for lipo-ed bundle
+    /// Output path for lipo-ed bundle. Defaults to universal-apple-darwin/<profile>
+    /// folder in cargo target directory when input bundles are located automatically.
     #[clap(long)]
-    out: PathBuf,
+    out: Option<PathBuf>,
+
+    #[clap(flatten)]
+    cargo: CargoArgs,
 
     /// Delete bundle in target directory (out-dir/BundleName.app) if already exists
-    #[clap(long)]
+    #[clap(long, overrides_with = "no_delete_existing_bundle")]
     delete_existing_bundle: bool,
+
+    /// Keep existing bundle in target directory (overrides configuration)
+    #[clap(long, overrides_with = "delete_existing_bundle")]
+    no_delete_existing_bundle: bool,
+
+    #[clap(flatten)]
+    copy: CopyArgs,
+}
+
+impl Options {
+    pub fn apply_config(&mut self, config: &UniversalConfig) {
+        if self.paths_in.is_empty() {
+            self.paths_in = config.paths_in.clone();
+        }
+        apply(&mut self.out, &config.out);
+        apply_flag(
+            &mut self.delete_existing_bundle,
+            self.no_delete_existing_bundle,
+            config.delete_existing_bundle,
+        );
+    }
+
+    pub fn set_profile(&mut self, profile: Option<String>) {
+        self.cargo.set_profile(profile);
+    }
 }
 
 pub struct Universal {
@@ -32,7 +67,19 @@ impl Universal {
         Self { options }
     }
 
-    pub fn perform(self) -> ToolResult<()> {
+    pub fn perform(mut self) -> ToolResult<()> {
+        if self.options.paths_in.is_empty() {
+            if self.options.cargo.targets().is_empty() {
+                return Err(ToolError::OtherError(
+                    "Missing input paths; specify them on command line, in configuration or use --target".into(),
+                ));
+            }
+            self.options.paths_in = self.options.cargo.find_app_bundles()?;
+            if self.options.out.is_none() {
+                let name = self.options.paths_in[0].file_name().unwrap();
+                self.options.out = Some(self.options.cargo.universal_dir()?.join(name));
+            }
+        }
         for path in &self.options.paths_in {
             if !path.exists() {
                 return Err(ToolError::OtherError(format!(
@@ -42,22 +89,27 @@ impl Universal {
             }
         }
 
-        if self.options.out.exists() {
-            if self.options.delete_existing_bundle {
-                fs::remove_dir_all(&self.options.out)
-                    .wrap_error(FileOperation::RemoveDir, || self.options.out.clone())?;
-            } else {
-                return Err(ToolError::OtherError(format!(
-                    "Target folder {:?} already exists. Please delete it first.",
-                    self.options.out
-                )));
-            }
+        let out = &required(&self.options.out, "--out")?;
+        if out.exists() && !self.options.delete_existing_bundle {
+            return Err(ToolError::OtherError(format!(
+                "Target folder {:?} already exists. Please delete it first.",
+                out
+            )));
+        }
+
+        if let Some(parent) = out.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).wrap_error(FileOperation::MkDir, || parent.into())?;
         }
 
-        fs::create_dir_all(&self.options.out)
-            .wrap_error(FileOperation::MkDir, || self.options.out.clone())?;
+        // Structure of the output follows the first bundle
+        if self.options.paths_in[0].is_dir() {
+            check_portability(&self.options.paths_in[0])?;
+        }
 
-        Self::process_dir(&self.options.paths_in, &self.options.out)
+        let staging = StagingDir::new(out)?;
+        let mut copier = Copier::new((&self.options.copy).into());
+        Self::process_dir(&mut copier, &self.options.paths_in, staging.path())?;
+        staging.commit(self.options.delete_existing_bundle)
     }
 
     // This is for checking whether binaries are same across all bundles, for which
@@ -76,7 +128,7 @@ impl Universal {
         Ok(sizes.all(|s| s == first_size))
     }
 
-    fn process_dir(paths_in: &[PathBuf], path_out: &Path) -> ToolResult<()> {
+    fn process_dir(copier: &mut Copier, paths_in: &[PathBuf], path_out: &Path) -> ToolResult<()> {
         let path = &paths_in[0];
         let paths_rest = &paths_in[1..];
         for entry in path
@@ -115,8 +167,14 @@ impl Universal {
                     .wrap_error(FileOperation::SymLink, || dest.clone())?;
             } else if meta.is_dir() {
                 fs::create_dir(&dest).wrap_error(FileOperation::CreateDir, || dest.clone())?;
-                Self::process_dir(&paths, &dest)?;
-            } else if is_executable_binary(&path)? && !Self::are_files_same(&paths)? {
+                Self::process_dir(copier, &paths, &dest)?;
+                copier.copy_metadata(&path, &dest).wrap_error_with_src(
+                    FileOperation::Copy,
+                    || dest.clone(),
+                    || path.clone(),
+                )?;
+            } else if classify_binary(&path)?.is_some() && !Self::are_files_same(&paths)? {
+                Self::check_binaries(&paths)?;
                 let mut cmd = std::process::Command::new("lipo");
                 cmd.arg("-create");
                 cmd.args(&paths);
@@ -133,8 +191,13 @@ impl Universal {
                         stdout: String::new(),
                     });
                 }
+                copier.copy_metadata(&path, &dest).wrap_error_with_src(
+                    FileOperation::Copy,
+                    || dest.clone(),
+                    || path.clone(),
+                )?;
             } else {
-                fs::copy(&path, &dest).wrap_error_with_src(
+                copier.copy_file(&path, &dest).wrap_error_with_src(
                     FileOperation::Copy,
                     || dest.clone(),
                     || path.clone(),
@@ -144,4 +207,30 @@ impl Universal {
 
         Ok(())
     }
+
+    // Binaries merged by lipo must be of same type and must not share architectures
+    fn check_binaries(paths: &[PathBuf]) -> ToolResult<()> {
+        let mut binary_type = None;
+        let mut archs = HashSet::new();
+        for path in paths {
+            let info = classify_binary(path)?.ok_or_else(|| {
+                ToolError::OtherError(format!("{:?} is not a Mach-O binary", path))
+            })?;
+            if *binary_type.get_or_insert(info.binary_type) != info.binary_type {
+                return Err(ToolError::OtherError(format!(
+                    "Binaries {:?} are of different types",
+                    paths
+                )));
+            }
+            for arch in info.archs {
+                if !archs.insert(arch.clone()) {
+                    return Err(ToolError::OtherError(format!(
+                        "Architecture {} is present in more than one of {:?}",
+                        arch, paths
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
 }
@@ -1,27 +1,178 @@
-use std::{fs::File, io::Read, path::Path};
-
-use crate::error::{FileOperation, IOResultExt, ToolResult};
-
-pub(super) fn is_executable_binary(path: &Path) -> ToolResult<bool> {
-    let meta = path
-        .metadata()
-        .wrap_error(FileOperation::Metadata, || path.into())?;
-    if meta.len() > 4 {
-        // Check for MACH-O magic
-        let mut f = File::open(path).wrap_error(FileOperation::Open, || path.into())?;
-        let mut start = [0; 4];
-        let num_read = f
-            .read(&mut start)
-            .wrap_error(FileOperation::Read, || path.into())?;
-
-        static MAGIC_FAT: &[u8] = &[0xca, 0xfe, 0xba, 0xbe];
-        static CIGAM_FAT: &[u8] = &[0xbe, 0xba, 0xfe, 0xca];
-        static MAGIC_64: &[u8] = &[0xfe, 0xed, 0xfa, 0xcf];
-        static CIGAM_64: &[u8] = &[0xcf, 0xfa, 0xed, 0xfe];
-
-        Ok(num_read == 4
-            && (start == MAGIC_FAT || start == CIGAM_FAT || start == MAGIC_64 || start == CIGAM_64))
-    } else {
-        Ok(false)
+use std::{
+    convert::TryInto,
+    fs::{self, File},
+    io::{ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+
+use crate::error::{FileOperation, IOResultExt, PlistResultExt, ToolError, ToolResult};
+
+use super::macho::{
+    MachFile, AR_MAGIC, FAT_MAGIC, FAT_MAGIC_64, MH_BUNDLE, MH_DSYM, MH_DYLIB, MH_EXECUTE,
+    MH_KEXT_BUNDLE, MH_MAGIC, MH_MAGIC_64, MH_OBJECT,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum BinaryType {
+    Executable,
+    Dylib,
+    Bundle,
+    KextBundle,
+    Dsym,
+    Object,
+    // Static library (possibly within fat wrapper)
+    Archive,
+    Other(u32),
+}
+
+impl BinaryType {
+    // Binaries loaded by dyld; These have dependencies that need to be bundled
+    pub fn is_loadable(self) -> bool {
+        matches!(
+            self,
+            BinaryType::Executable | BinaryType::Dylib | BinaryType::Bundle
+        )
+    }
+
+    // Binaries that carry code signature
+    pub fn is_signable(self) -> bool {
+        self.is_loadable() || self == BinaryType::KextBundle
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct BinaryInfo {
+    pub binary_type: BinaryType,
+    pub archs: Vec<String>,
+    pub signed: bool,
+}
+
+// Classifies Mach-O file (thin, fat or static archive). Returns None for other files.
+pub(super) fn classify_binary(path: &Path) -> ToolResult<Option<BinaryInfo>> {
+    let mut f = File::open(path).wrap_error(FileOperation::Open, || path.into())?;
+    let mut start = [0; 8];
+    let num_read = f
+        .read(&mut start)
+        .wrap_error(FileOperation::Read, || path.into())?;
+    let start = &start[..num_read];
+
+    if start == AR_MAGIC {
+        return Ok(Some(BinaryInfo {
+            binary_type: BinaryType::Archive,
+            archs: Vec::new(),
+            signed: false,
+        }));
+    }
+    let magic = match start.get(..4) {
+        Some(magic) => u32::from_be_bytes(magic.try_into().unwrap()),
+        None => return Ok(None),
+    };
+    if ![MH_MAGIC, MH_MAGIC_64, FAT_MAGIC, FAT_MAGIC_64]
+        .iter()
+        .any(|m| *m == magic || m.swap_bytes() == magic)
+    {
+        return Ok(None);
+    }
+
+    let data = fs::read(path).wrap_error(FileOperation::Read, || path.into())?;
+    let file = match MachFile::parse(data) {
+        Ok(Some(file)) => file,
+        Ok(None) => return Ok(None),
+        // Fat magic is shared with Java class files
+        Err(err) => {
+            debug!("{:?}: not a Mach-O file ({})", path, err);
+            return Ok(None);
+        }
+    };
+    let binary_type = match file.slices().next().map(|s| s.file_type()) {
+        Some(MH_EXECUTE) => BinaryType::Executable,
+        Some(MH_DYLIB) => BinaryType::Dylib,
+        Some(MH_BUNDLE) => BinaryType::Bundle,
+        Some(MH_KEXT_BUNDLE) => BinaryType::KextBundle,
+        Some(MH_DSYM) => BinaryType::Dsym,
+        Some(MH_OBJECT) => BinaryType::Object,
+        Some(file_type) => BinaryType::Other(file_type),
+        None if file.raw_slices().any(|s| s.starts_with(AR_MAGIC)) => BinaryType::Archive,
+        None => return Ok(None),
+    };
+    let signed = file.slices().any(|s| s.is_signed());
+    Ok(Some(BinaryInfo {
+        binary_type,
+        archs: file.archs(),
+        signed,
+    }))
+}
+
+// Returns whether file content contains given byte sequence
+pub(super) fn file_contains(path: &Path, needle: &[u8]) -> ToolResult<bool> {
+    let data = fs::read(path).wrap_error(FileOperation::Read, || path.into())?;
+    Ok(data.windows(needle.len()).any(|w| w == needle))
+}
+
+// Returns string value for given key from Info.plist
+pub(super) fn info_plist_string(info_plist: &Path, key: &str) -> ToolResult<Option<String>> {
+    let plist = plist::Value::from_file(info_plist).wrap_error(|| Some(info_plist.into()))?;
+    Ok(plist
+        .as_dictionary()
+        .and_then(|d| d.get(key))
+        .and_then(|v| v.as_string())
+        .map(|s| s.into()))
+}
+
+// Collects loadable Mach-O binaries (executables, dylibs and bundles) in given folder
+// recursively; Result is sorted and symlinks are skipped.
+pub(super) fn find_binaries(dir: &Path, binaries: &mut Vec<PathBuf>) -> ToolResult<()> {
+    let mut entries = Vec::new();
+    for entry in dir
+        .read_dir()
+        .wrap_error(FileOperation::ReadDir, || dir.into())?
+    {
+        let entry = entry.wrap_error(FileOperation::Read, || dir.into())?;
+        entries.push(entry.path());
+    }
+    entries.sort();
+    for path in entries {
+        let meta = path
+            .symlink_metadata()
+            .wrap_error(FileOperation::Metadata, || path.clone())?;
+        if meta.is_dir() {
+            find_binaries(&path, binaries)?;
+        } else if meta.is_file() {
+            if let Some(info) = classify_binary(&path)? {
+                if info.binary_type.is_loadable() {
+                    binaries.push(path);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Writes report to standard output; Output is often piped to other tools, so closed
+// pipe is not an error.
+pub(super) fn write_stdout(out: &str) -> ToolResult<()> {
+    match std::io::stdout().write_all(out.as_bytes()) {
+        Err(err) if err.kind() != ErrorKind::BrokenPipe => Err(ToolError::OtherError(format!(
+            "Failed to write output: {}",
+            err
+        ))),
+        _ => Ok(()),
+    }
+}
+
+// Human readable file size (1024 based)
+pub(super) fn format_size(size: u64) -> String {
+    const UNITS: &[&str] = &["KB", "MB", "GB"];
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
     }
+    format!("{:.1} {}", value, UNITS[unit])
 }
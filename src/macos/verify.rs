@@ -0,0 +1,492 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use log::{debug, info, trace};
+
+use crate::{
+    config::{apply, required, VerifyConfig},
+    error::{FileOperation, IOResultExt, LinkIssue, LinkProblem, ToolError, ToolResult},
+};
+
+use super::{
+    bundle::ModulePath,
+    macho::{
+        MachFile, Slice, DYNAMIC_LOOKUP_ORDINAL, EXECUTABLE_ORDINAL, LC_REEXPORT_DYLIB, MH_BUNDLE,
+        MH_DYLIB, MH_EXECUTE, SELF_LIBRARY_ORDINAL,
+    },
+    tbd::SdkStubs,
+    utils::{find_binaries, info_plist_string},
+};
+
+#[derive(clap::Parser)]
+pub struct Options {
+    /// Path to self-contained bundle produced by the macos-bundle command
+    bundle_path: Option<PathBuf>,
+
+    /// Folder with text-based stubs (.tbd) of macOS SDK for the deployment target
+    /// (i.e. MacOSX11.3.sdk). When specified, system dependencies are verified as well.
+    #[clap(long)]
+    sdk_stubs: Option<PathBuf>,
+}
+
+impl Options {
+    pub fn apply_config(&mut self, config: &VerifyConfig) {
+        apply(&mut self.bundle_path, &config.bundle_path);
+        apply(&mut self.sdk_stubs, &config.sdk_stubs);
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Library {
+    // Library within bundle
+    Bundled(PathBuf),
+    // System library (install name)
+    System(String),
+}
+
+// Symbols exported by single architecture of a library
+struct Exports {
+    symbols: HashSet<String>,
+    reexports: Vec<Library>,
+}
+
+#[derive(Clone)]
+enum Lookup {
+    Found(Rc<Exports>),
+    MissingArchitecture,
+    NotFound,
+    // System library without SDK stubs to verify against
+    Unverified,
+}
+
+// Main executable of the process a binary is loaded into; @executable_path and
+// executable rpaths are resolved against it.
+struct Host {
+    executable_dir: PathBuf,
+    rpaths: Vec<String>,
+}
+
+pub struct Verify {
+    options: Options,
+    bundle_path: PathBuf,
+    // Hosts by executable path
+    hosts: HashMap<PathBuf, Rc<Host>>,
+    sdk_stubs: Option<SdkStubs>,
+    exports: HashMap<(Library, String), Lookup>,
+    issues: Vec<LinkIssue>,
+}
+
+impl Verify {
+    pub fn new(options: Options) -> Self {
+        Self {
+            options,
+            bundle_path: PathBuf::new(),
+            hosts: HashMap::new(),
+            sdk_stubs: None,
+            exports: HashMap::new(),
+            issues: Vec::new(),
+        }
+    }
+
+    //
+    // Verifies that all binaries in bundle link:
+    //   - every non-system dependency must resolve to a library within the bundle that
+    //     contains matching architecture (weak dependencies may be missing)
+    //   - with SDK stubs, every system dependency must exist in the SDK and contain
+    //     matching architecture
+    //   - every symbol imported through two-level namespace from such library must be
+    //     exported by it (or by library it re-exports); weak imports may be missing
+    //
+    // @rpath is resolved using rpaths of the binary and rpaths of the executable it is
+    // loaded into: the main bundle executable, or executable of the nested app or XPC
+    // service containing the binary.
+    //
+    pub fn perform(mut self) -> ToolResult<()> {
+        let bundle_path = required(&self.options.bundle_path, "bundle path")?;
+        self.bundle_path = bundle_path
+            .canonicalize()
+            .wrap_error(FileOperation::Canonicalize, || bundle_path.clone())?;
+        if let Some(sdk_stubs) = &self.options.sdk_stubs {
+            self.sdk_stubs = Some(SdkStubs::new(sdk_stubs)?);
+        }
+
+        let mut binaries = Vec::new();
+        find_binaries(&self.bundle_path, &mut binaries)?;
+        for binary in binaries {
+            self.verify_binary(&binary)?;
+        }
+
+        if self.issues.is_empty() {
+            info!("All bundle dependencies resolved");
+            Ok(())
+        } else {
+            Err(ToolError::LinkIssues(self.issues))
+        }
+    }
+
+    fn verify_binary(&mut self, path: &Path) -> ToolResult<()> {
+        let file = match MachFile::read(path)? {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        let relative = pathdiff::diff_paths(path, &self.bundle_path).unwrap();
+        for slice in file.slices() {
+            if !matches!(slice.file_type(), MH_EXECUTE | MH_DYLIB | MH_BUNDLE) {
+                continue;
+            }
+            let arch = slice.arch_name();
+            debug!("Verifying {:?} ({})", relative, arch);
+            let host = self.host(path, &slice)?;
+
+            let references = slice.dylib_references();
+            let mut libraries = Vec::new();
+            for reference in &references {
+                let lookup = match self.locate(path, &slice, &host, &reference.name) {
+                    Some(library) => self.exports(&library, &arch)?,
+                    None => Lookup::NotFound,
+                };
+                let problem = match &lookup {
+                    Lookup::NotFound if reference.is_weak() => None,
+                    Lookup::NotFound => Some(LinkProblem::LibraryNotFound),
+                    Lookup::MissingArchitecture => Some(LinkProblem::MissingArchitecture),
+                    _ => None,
+                };
+                if let Some(problem) = problem {
+                    self.issues.push(LinkIssue {
+                        path: relative.clone(),
+                        arch: arch.clone(),
+                        library: reference.name.clone(),
+                        problem,
+                    });
+                }
+                libraries.push(lookup);
+            }
+
+            // Flat namespace symbols are not bound to particular library
+            if !slice.is_two_level() {
+                continue;
+            }
+
+            let mut missing = BTreeMap::<usize, Vec<String>>::new();
+            for symbol in slice.imported_symbols() {
+                if symbol.weak
+                    || matches!(
+                        symbol.library_ordinal,
+                        SELF_LIBRARY_ORDINAL | DYNAMIC_LOOKUP_ORDINAL | EXECUTABLE_ORDINAL
+                    )
+                {
+                    continue;
+                }
+                let index = symbol.library_ordinal as usize - 1;
+                let exports = match libraries.get(index) {
+                    Some(Lookup::Found(exports)) => exports.clone(),
+                    _ => continue,
+                };
+                if !self.is_exported(&exports, &symbol.name, &arch, 0)? {
+                    trace!("Unresolved symbol {} in {:?}", symbol.name, relative);
+                    missing.entry(index).or_default().push(symbol.name);
+                }
+            }
+            for (index, symbols) in missing {
+                self.issues.push(LinkIssue {
+                    path: relative.clone(),
+                    arch: arch.clone(),
+                    library: references[index].name.clone(),
+                    problem: LinkProblem::MissingSymbols(symbols),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn is_exported(
+        &mut self,
+        exports: &Exports,
+        symbol: &str,
+        arch: &str,
+        depth: usize,
+    ) -> ToolResult<bool> {
+        if exports.symbols.contains(symbol) {
+            return Ok(true);
+        }
+        if depth > 16 {
+            return Ok(false);
+        }
+        for reexport in &exports.reexports {
+            let exported = match self.exports(reexport, arch)? {
+                Lookup::Found(reexport) => self.is_exported(&reexport, symbol, arch, depth + 1)?,
+                // Can't verify symbols of this library
+                Lookup::Unverified => true,
+                _ => false,
+            };
+            if exported {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn exports(&mut self, library: &Library, arch: &str) -> ToolResult<Lookup> {
+        let key = (library.clone(), arch.to_owned());
+        if let Some(lookup) = self.exports.get(&key) {
+            return Ok(lookup.clone());
+        }
+        let lookup = match library {
+            Library::Bundled(path) => self.bundled_exports(path, arch)?,
+            Library::System(install_name) => self.system_exports(install_name, arch)?,
+        };
+        self.exports.insert(key, lookup.clone());
+        Ok(lookup)
+    }
+
+    fn bundled_exports(&mut self, path: &Path, arch: &str) -> ToolResult<Lookup> {
+        let file = MachFile::read(path)?;
+        let slice = match file
+            .as_ref()
+            .and_then(|f| f.slices().find(|s| s.arch_name() == arch))
+        {
+            Some(slice) => slice,
+            None => return Ok(Lookup::MissingArchitecture),
+        };
+        let host = self.host(path, &slice)?;
+        let reexports = slice
+            .dylib_references()
+            .iter()
+            .filter(|r| r.cmd == LC_REEXPORT_DYLIB)
+            .filter_map(|r| self.locate(path, &slice, &host, &r.name))
+            .collect();
+        Ok(Lookup::Found(Rc::new(Exports {
+            symbols: slice
+                .exported_symbols()
+                .map_err(|e| {
+                    ToolError::OtherError(format!("Malformed Mach-O file {:?}: {}", path, e))
+                })?
+                .into_iter()
+                .collect(),
+            reexports,
+        })))
+    }
+
+    fn system_exports(&mut self, install_name: &str, arch: &str) -> ToolResult<Lookup> {
+        let sdk_stubs = match &mut self.sdk_stubs {
+            Some(sdk_stubs) => sdk_stubs,
+            None => return Ok(Lookup::Unverified),
+        };
+        let stub = match sdk_stubs.find(install_name)? {
+            Some(stub) => stub,
+            None => return Ok(Lookup::NotFound),
+        };
+        match stub.symbols(arch) {
+            Some(symbols) => Ok(Lookup::Found(Rc::new(Exports {
+                symbols: symbols.clone(),
+                reexports: stub
+                    .reexported_libraries
+                    .iter()
+                    .map(|l| Library::System(l.clone()))
+                    .collect(),
+            }))),
+            None => Ok(Lookup::MissingArchitecture),
+        }
+    }
+
+    // Executables are their own host; Other binaries are loaded into executable of the
+    // innermost app or XPC bundle containing them (so plugins and frameworks of the
+    // main bundle use main bundle executable).
+    fn host(&mut self, path: &Path, slice: &Slice) -> ToolResult<Rc<Host>> {
+        let executable = if slice.file_type() == MH_EXECUTE {
+            Some(path.to_path_buf())
+        } else {
+            self.bundle_executable(path)?
+        };
+        let executable = match executable {
+            Some(executable) => executable,
+            None => {
+                return Ok(Rc::new(Host {
+                    executable_dir: self.bundle_path.join("Contents").join("MacOS"),
+                    rpaths: Vec::new(),
+                }))
+            }
+        };
+        if let Some(host) = self.hosts.get(&executable) {
+            return Ok(host.clone());
+        }
+        let rpaths = match MachFile::read(&executable)? {
+            Some(file) => file.slices().next().map(|s| s.rpaths()).unwrap_or_default(),
+            None => Vec::new(),
+        };
+        let host = Rc::new(Host {
+            executable_dir: executable.parent().unwrap().into(),
+            rpaths,
+        });
+        self.hosts.insert(executable, host.clone());
+        Ok(host)
+    }
+
+    // Executable of the innermost app or XPC bundle (or main bundle) containing given
+    // binary
+    fn bundle_executable(&self, path: &Path) -> ToolResult<Option<PathBuf>> {
+        for bundle in path.ancestors().skip(1) {
+            if !bundle.starts_with(&self.bundle_path) {
+                break;
+            }
+            let is_host = bundle == self.bundle_path
+                || bundle
+                    .extension()
+                    .map(|e| e == "app" || e == "xpc")
+                    .unwrap_or(false);
+            let info_plist = bundle.join("Contents").join("Info.plist");
+            if !is_host || !info_plist.is_file() {
+                continue;
+            }
+            if let Some(executable) = info_plist_string(&info_plist, "CFBundleExecutable")? {
+                let executable = bundle.join("Contents").join("MacOS").join(executable);
+                return Ok(Some(executable).filter(|e| e.is_file()));
+            }
+        }
+        Ok(None)
+    }
+
+    // Locates dependency of binary at given path. Libraries not found in bundle are
+    // considered system libraries if the install name refers to a system location.
+    fn locate(&self, path: &Path, slice: &Slice, host: &Host, name: &str) -> Option<Library> {
+        if let Some(path) = self.resolve(path, slice, host, name) {
+            return Some(Library::Bundled(path));
+        }
+        let module = ModulePath::new(name.into());
+        if module.is_system() || module.is_swift_runtime() {
+            Some(Library::System(name.into()))
+        } else {
+            None
+        }
+    }
+
+    // Resolves dependency of binary at given path to a library within bundle
+    fn resolve(&self, path: &Path, slice: &Slice, host: &Host, name: &str) -> Option<PathBuf> {
+        let candidates: Vec<PathBuf> = if let Some(name) = name.strip_prefix("@rpath/") {
+            slice
+                .rpaths()
+                .iter()
+                .chain(host.rpaths.iter())
+                .filter_map(|rpath| self.expand(path, host, rpath))
+                .map(|rpath| rpath.join(name))
+                .collect()
+        } else {
+            self.expand(path, host, name).into_iter().collect()
+        };
+        candidates
+            .iter()
+            .filter_map(|c| c.canonicalize().ok())
+            .find(|c| c.starts_with(&self.bundle_path) && c.is_file())
+    }
+
+    fn expand(&self, path: &Path, host: &Host, name: &str) -> Option<PathBuf> {
+        if let Some(rest) = name.strip_prefix("@loader_path") {
+            Some(PathBuf::from(format!(
+                "{}{}",
+                path.parent()?.display(),
+                rest
+            )))
+        } else if let Some(rest) = name.strip_prefix("@executable_path") {
+            Some(PathBuf::from(format!(
+                "{}{}",
+                host.executable_dir.display(),
+                rest
+            )))
+        } else if name.starts_with('@') {
+            None
+        } else {
+            Some(name.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        macos::macho::{test_fixtures::*, LC_ID_DYLIB, LC_LOAD_DYLIB, LC_RPATH},
+        utils::temp_dir,
+    };
+
+    fn write_bundle(bundle_path: &Path, executable: &str, binary: Vec<u8>) {
+        let macos = bundle_path.join("Contents/MacOS");
+        fs::create_dir_all(&macos).unwrap();
+        fs::write(macos.join(executable), binary).unwrap();
+        let mut info = plist::Dictionary::new();
+        info.insert("CFBundleExecutable".into(), executable.into());
+        plist::Value::Dictionary(info)
+            .to_file_xml(bundle_path.join("Contents/Info.plist"))
+            .unwrap();
+    }
+
+    fn dylib(install_name: &str, dependencies: &[&str]) -> Vec<u8> {
+        let mut commands = vec![string_command(LC_ID_DYLIB, 3, install_name)];
+        for dependency in dependencies {
+            commands.push(string_command(LC_LOAD_DYLIB, 3, dependency));
+        }
+        macho(CPU_TYPE_ARM64, MH_DYLIB, &commands)
+    }
+
+    fn verify(bundle_path: &Path) -> ToolResult<()> {
+        Verify::new(Options {
+            bundle_path: Some(bundle_path.into()),
+            sdk_stubs: None,
+        })
+        .perform()
+    }
+
+    #[test]
+    fn resolves_executable_path_of_xpc_service() {
+        let dir = temp_dir().unwrap();
+        let bundle_path = dir.join("Main.app");
+        write_bundle(&bundle_path, "Main", macho(CPU_TYPE_ARM64, MH_EXECUTE, &[]));
+        let service = bundle_path.join("Contents/XPCServices/Service.xpc");
+        write_bundle(
+            &service,
+            "Service",
+            macho(
+                CPU_TYPE_ARM64,
+                MH_EXECUTE,
+                &[
+                    string_command(LC_LOAD_DYLIB, 3, "@rpath/libfoo.dylib"),
+                    string_command(LC_RPATH, 0, "@executable_path/../Frameworks"),
+                ],
+            ),
+        );
+        let frameworks = service.join("Contents/Frameworks");
+        fs::create_dir_all(&frameworks).unwrap();
+        // Library loaded into the service resolves @executable_path against it as well
+        fs::write(
+            frameworks.join("libfoo.dylib"),
+            dylib(
+                "@rpath/libfoo.dylib",
+                &["@executable_path/../Frameworks/libbar.dylib"],
+            ),
+        )
+        .unwrap();
+        match verify(&bundle_path) {
+            Err(ToolError::LinkIssues(issues)) => {
+                let libraries: Vec<_> = issues.iter().map(|i| i.library.as_str()).collect();
+                assert_eq!(
+                    libraries,
+                    vec!["@executable_path/../Frameworks/libbar.dylib"]
+                );
+            }
+            _ => panic!("Expected missing libbar.dylib"),
+        }
+
+        fs::write(
+            frameworks.join("libbar.dylib"),
+            dylib("@rpath/libbar.dylib", &[]),
+        )
+        .unwrap();
+        verify(&bundle_path).unwrap();
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
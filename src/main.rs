@@ -1,9 +1,15 @@
+mod cargo;
+mod config;
 #[allow(dead_code)]
 mod error;
 mod macos;
 mod utils;
 
+use std::path::PathBuf;
+
 use clap::Parser;
+use config::Config;
+use error::ToolResult;
 use simple_logger::SimpleLogger;
 
 #[derive(Parser)]
@@ -12,12 +18,20 @@ struct Opts {
     /// A level of verbosity, and can be used multiple times
     #[clap(short = 'v', long = "verbose", parse(from_occurrences))]
     verbose: i32,
+    /// Configuration file. Defaults to bundle_tool.toml in current directory or
+    /// [package.metadata.bundle-tool] in Cargo.toml of current crate
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+    /// Configuration profile (i.e. dev, release). Also selects cargo profile when
+    /// locating build artifacts
+    #[clap(long, global = true)]
+    profile: Option<String>,
     #[clap(subcommand)]
     subcmd: SubCommand,
 }
 
 #[derive(Parser)]
-#[allow(clippy::enum_variant_names)]
+#[allow(clippy::enum_variant_names, clippy::large_enum_variant)]
 enum SubCommand {
     /// Creates a self-contained macOS bundle
     #[clap(name = "macos-bundle")]
@@ -32,6 +46,34 @@ enum SubCommand {
 
     #[clap(name = "macos-universal")]
     MacOSUniversal(macos::universal::Options),
+
+    /// Sets or merges Info.plist keys of a bundle
+    #[clap(name = "macos-plist")]
+    MacOSPlist(macos::info_plist::Options),
+
+    /// Generates .icns icon from PNG images (into a bundle or standalone file)
+    #[clap(name = "macos-icon")]
+    MacOSIcon(macos::icon::Options),
+
+    /// Compares two bundles (i.e. previous and new release)
+    #[clap(name = "macos-diff")]
+    MacOSDiff(macos::diff::Options),
+
+    /// Prints bundle content with details of binaries and plists
+    #[clap(name = "macos-inspect")]
+    MacOSInspect(macos::inspect::Options),
+
+    /// Checks Info.plist and structure of a bundle
+    #[clap(name = "macos-lint")]
+    MacOSLint(macos::lint::Options),
+
+    /// Reports bundle size breakdown and duplicate files
+    #[clap(name = "macos-size")]
+    MacOSSize(macos::size::Options),
+
+    /// Verifies that all dependencies and imported symbols of bundle binaries resolve
+    #[clap(name = "macos-verify")]
+    MacOSVerify(macos::verify::Options),
 }
 
 fn main() {
@@ -54,15 +96,51 @@ fn main() {
 
     SimpleLogger::new().with_level(log_level).init().unwrap();
 
-    let res = match opts.subcmd {
-        SubCommand::MacOSBundle(options) => macos::bundle::SelfContained::new(options).perform(),
-        SubCommand::MacOSCodesign(options) => macos::codesign::CodeSign::new(options).perform(),
-        SubCommand::MacOSNotarize(options) => macos::notarize::Notarize::new(options).perform(),
-        SubCommand::MacOSUniversal(options) => macos::universal::Universal::new(options).perform(),
-    };
-
-    if let Err(error) = res {
+    if let Err(error) = run(opts) {
         eprintln!("\n** Tool failed with error **\n\n{}", error);
         std::process::exit(1);
     }
 }
+
+fn run(opts: Opts) -> ToolResult<()> {
+    let config = Config::load(opts.config.as_deref())?.resolve(opts.profile.as_deref())?;
+    match opts.subcmd {
+        SubCommand::MacOSBundle(mut options) => {
+            options.apply_config(&config.bundle);
+            options.set_profile(opts.profile);
+            macos::bundle::SelfContained::new(options).perform()
+        }
+        SubCommand::MacOSCodesign(mut options) => {
+            options.apply_config(&config.codesign);
+            macos::codesign::CodeSign::new(options).perform()
+        }
+        SubCommand::MacOSNotarize(mut options) => {
+            options.apply_config(&config.notarize);
+            macos::notarize::Notarize::new(options).perform()
+        }
+        SubCommand::MacOSUniversal(mut options) => {
+            options.apply_config(&config.universal);
+            options.set_profile(opts.profile);
+            macos::universal::Universal::new(options).perform()
+        }
+        SubCommand::MacOSPlist(mut options) => {
+            options.apply_config(&config.bundle);
+            macos::info_plist::InfoPlist::new(options).perform()
+        }
+        SubCommand::MacOSIcon(mut options) => {
+            options.apply_config(&config.bundle);
+            macos::icon::Icon::new(options).perform()
+        }
+        SubCommand::MacOSDiff(options) => macos::diff::Diff::new(options).perform(),
+        SubCommand::MacOSInspect(options) => macos::inspect::Inspect::new(options).perform(),
+        SubCommand::MacOSLint(mut options) => {
+            options.apply_config(&config.lint);
+            macos::lint::Lint::new(options).perform()
+        }
+        SubCommand::MacOSSize(options) => macos::size::Size::new(options).perform(),
+        SubCommand::MacOSVerify(mut options) => {
+            options.apply_config(&config.verify);
+            macos::verify::Verify::new(options).perform()
+        }
+    }
+}
@@ -1,11 +1,16 @@
 use std::{
+    collections::HashMap,
+    ffi::OsStr,
     fs::{self, File},
     io::{self, Read},
-    os::unix::prelude::MetadataExt,
-    path::Path,
+    os::unix::prelude::{MetadataExt, PermissionsExt},
+    path::{Component, Path, PathBuf},
     process::Command,
 };
 
+use filetime::FileTime;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+
 use crate::error::{FileOperation, IOResultExt, ToolError, ToolResult};
 
 pub(super) fn run_command(mut command: Command, command_name: &str) -> ToolResult<Vec<String>> {
@@ -66,29 +71,625 @@ pub fn is_same(f1: &Path, f2: &Path) -> ToolResult<bool> {
     Ok(diff_files(&mut f1, &mut f2))
 }
 
-// Copies source directory to destination, preserving symlinks
-pub fn copy_dir(src_dir: &Path, dest_dir: &Path) -> io::Result<()> {
-    fs::create_dir(dest_dir)?;
-    for entry in src_dir.read_dir()? {
-        let entry = entry?;
-        let meta = entry.path().symlink_metadata()?;
-        let dest = dest_dir.join(entry.file_name());
-        if meta.file_type().is_symlink() {
-            let link = entry.path().read_link()?;
-            std::os::unix::fs::symlink(&link, &dest)?;
+// Extended attributes that are stripped from copied files by default
+pub const DEFAULT_STRIPPED_XATTRS: &[&str] = &["com.apple.quarantine", "com.apple.FinderInfo"];
+
+#[derive(clap::Parser)]
+pub struct CopyArgs {
+    /// Do not preserve permission bits of copied files and directories
+    #[clap(long)]
+    no_preserve_mode: bool,
+
+    /// Do not preserve modification and access times of copied files
+    #[clap(long)]
+    no_preserve_times: bool,
+
+    /// Do not preserve extended attributes of copied files
+    #[clap(long)]
+    no_preserve_xattrs: bool,
+
+    /// Extended attribute to strip from copied files. Can be specified multiple
+    /// times. Defaults to com.apple.quarantine and com.apple.FinderInfo.
+    #[clap(long)]
+    strip_xattr: Vec<String>,
+
+    /// Copy hardlinked files as separate files
+    #[clap(long)]
+    no_preserve_hardlinks: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct CopyOptions {
+    pub preserve_mode: bool,
+    pub preserve_times: bool,
+    pub preserve_xattrs: bool,
+    pub strip_xattrs: Vec<String>,
+    pub preserve_hardlinks: bool,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        Self {
+            preserve_mode: true,
+            preserve_times: true,
+            preserve_xattrs: true,
+            strip_xattrs: DEFAULT_STRIPPED_XATTRS
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            preserve_hardlinks: true,
+        }
+    }
+}
+
+impl From<&CopyArgs> for CopyOptions {
+    fn from(args: &CopyArgs) -> Self {
+        let mut res = Self {
+            preserve_mode: !args.no_preserve_mode,
+            preserve_times: !args.no_preserve_times,
+            preserve_xattrs: !args.no_preserve_xattrs,
+            preserve_hardlinks: !args.no_preserve_hardlinks,
+            ..Default::default()
+        };
+        if !args.strip_xattr.is_empty() {
+            res.strip_xattrs = args.strip_xattr.clone();
+        }
+        res
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Copied {
+    File,
+    HardLink,
+}
+
+// Copies files and directories while preserving metadata according to CopyOptions.
+// Keeps track of already copied inodes so that hardlinks within the source tree
+// can be recreated in destination.
+pub struct Copier {
+    options: CopyOptions,
+    hardlinks: HashMap<(u64, u64), PathBuf>,
+}
+
+impl Copier {
+    pub fn new(options: CopyOptions) -> Self {
+        Self {
+            options,
+            hardlinks: HashMap::new(),
+        }
+    }
+
+    // Copies source directory to destination, preserving symlinks
+    pub fn copy_dir(&mut self, src_dir: &Path, dest_dir: &Path) -> io::Result<()> {
+        fs::create_dir(dest_dir)?;
+        for entry in src_dir.read_dir()? {
+            let entry = entry?;
+            let meta = entry.path().symlink_metadata()?;
+            let dest = dest_dir.join(entry.file_name());
+            if meta.file_type().is_symlink() {
+                let link = entry.path().read_link()?;
+                std::os::unix::fs::symlink(&link, &dest)?;
+                if self.options.preserve_times {
+                    let mtime = FileTime::from_last_modification_time(&meta);
+                    let atime = FileTime::from_last_access_time(&meta);
+                    filetime::set_symlink_file_times(&dest, atime, mtime)?;
+                }
+            } else {
+                self.copy(&entry.path(), &dest)?;
+            }
+        }
+        self.copy_metadata(src_dir, dest_dir)
+    }
+
+    pub fn copy(&mut self, src: &Path, dest: &Path) -> io::Result<()> {
+        if src.is_dir() {
+            self.copy_dir(src, dest)?;
         } else {
-            copy(&entry.path(), &dest)?;
+            self.copy_file(src, dest)?;
         }
+        Ok(())
     }
 
-    Ok(())
+    // Copies single file. If the file is hardlinked to previously copied file, the hardlink
+    // is recreated instead.
+    pub fn copy_file(&mut self, src: &Path, dest: &Path) -> io::Result<Copied> {
+        let meta = src.metadata()?;
+        let key = (meta.dev(), meta.ino());
+        if self.options.preserve_hardlinks && meta.nlink() > 1 {
+            if let Some(existing) = self.hardlinks.get(&key) {
+                fs::hard_link(existing, dest)?;
+                return Ok(Copied::HardLink);
+            }
+        }
+        fs::copy(src, dest)?;
+        self.copy_metadata(src, dest)?;
+        if self.options.preserve_hardlinks && meta.nlink() > 1 {
+            self.hardlinks.insert(key, dest.into());
+        }
+        Ok(Copied::File)
+    }
+
+    // Applies metadata from source to already copied file or directory. Extended
+    // attributes are written first, while destination is still writable; fs::copy
+    // copies permissions (and on macOS extended attributes) of the source.
+    pub fn copy_metadata(&self, src: &Path, dest: &Path) -> io::Result<()> {
+        let meta = src.metadata()?;
+        let dest_mode = dest.metadata()?.mode();
+        if dest_mode & 0o200 == 0 {
+            fs::set_permissions(dest, fs::Permissions::from_mode(dest_mode | 0o200))?;
+        }
+        if self.options.preserve_xattrs {
+            for name in ignore_unsupported(xattr::list(src))?.into_iter().flatten() {
+                if self.is_stripped(&name) {
+                    continue;
+                }
+                if let Some(value) = xattr::get(src, &name)? {
+                    xattr::set(dest, &name, &value)?;
+                }
+            }
+            for name in &self.options.strip_xattrs {
+                if let Some(Some(_)) = ignore_unsupported(xattr::get(dest, name))? {
+                    xattr::remove(dest, name)?;
+                }
+            }
+        } else {
+            for name in ignore_unsupported(xattr::list(dest))?.into_iter().flatten() {
+                xattr::remove(dest, &name)?;
+            }
+        }
+        if self.options.preserve_mode {
+            fs::set_permissions(dest, meta.permissions())?;
+        } else {
+            let mode = if meta.is_dir() || meta.mode() & 0o111 != 0 {
+                0o755
+            } else {
+                0o644
+            };
+            fs::set_permissions(dest, fs::Permissions::from_mode(mode))?;
+        }
+        if self.options.preserve_times {
+            let mtime = FileTime::from_last_modification_time(&meta);
+            let atime = FileTime::from_last_access_time(&meta);
+            filetime::set_file_times(dest, atime, mtime)?;
+        }
+        Ok(())
+    }
+
+    fn is_stripped(&self, name: &OsStr) -> bool {
+        self.options.strip_xattrs.iter().any(|s| name == s.as_str())
+    }
+}
+
+// Filesystems without extended attribute support are treated as having no attributes
+fn ignore_unsupported<T>(res: io::Result<T>) -> io::Result<Option<T>> {
+    match res {
+        Ok(res) => Ok(Some(res)),
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// Output folder that is built in a sibling staging directory and moved into place
+// only after it is complete. Staging directory is removed when dropped without commit.
+pub struct StagingDir {
+    target: PathBuf,
+    staging: PathBuf,
+    committed: bool,
+}
+
+impl StagingDir {
+    pub fn new(target: &Path) -> ToolResult<Self> {
+        let staging = sibling_path(target, "staging")?;
+        fs::create_dir(&staging).wrap_error(FileOperation::MkDir, || staging.clone())?;
+        Ok(Self {
+            target: target.into(),
+            staging,
+            committed: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.staging
+    }
+
+    // Moves staging directory to target location. Existing target is only removed
+    // after the new content is in place.
+    pub fn commit(mut self, replace_existing: bool) -> ToolResult<()> {
+        let previous = if self.target.symlink_metadata().is_ok() {
+            if !replace_existing {
+                return Err(ToolError::OtherError(format!(
+                    "Target folder {:?} already exists. Please delete it first.",
+                    self.target
+                )));
+            }
+            let previous = sibling_path(&self.target, "previous")?;
+            fs::rename(&self.target, &previous).wrap_error_with_src(
+                FileOperation::Move,
+                || previous.clone(),
+                || self.target.clone(),
+            )?;
+            Some(previous)
+        } else {
+            None
+        };
+        if let Err(error) = fs::rename(&self.staging, &self.target) {
+            if let Some(previous) = &previous {
+                fs::rename(previous, &self.target).ok();
+            }
+            return Err(error).wrap_error_with_src(
+                FileOperation::Move,
+                || self.target.clone(),
+                || self.staging.clone(),
+            );
+        }
+        self.committed = true;
+        if let Some(previous) = previous {
+            fs::remove_dir_all(&previous).wrap_error(FileOperation::RemoveDir, || previous)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        if !self.committed {
+            fs::remove_dir_all(&self.staging).ok();
+        }
+    }
 }
 
-pub fn copy(src: &Path, dest: &Path) -> io::Result<()> {
-    if src.is_dir() {
-        copy_dir(src, dest)?;
+// Target must end with a folder name (not ".", ".." or "/"), as it is replaced
+// by renaming the sibling.
+fn sibling_path(path: &Path, kind: &str) -> ToolResult<PathBuf> {
+    let file_name = path.file_name().ok_or_else(|| {
+        ToolError::OtherError(format!(
+            "Invalid output path {:?}; Path must end with a folder name",
+            path
+        ))
+    })?;
+    let name = format!(
+        ".{}.{}-{}",
+        file_name.to_string_lossy(),
+        kind,
+        random_string()
+    );
+    Ok(path.with_file_name(name))
+}
+
+fn random_string() -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(10)
+        .map(char::from)
+        .collect()
+}
+
+// Creates new randomly named directory inside system temp directory
+pub fn temp_dir() -> ToolResult<PathBuf> {
+    let path = std::env::temp_dir().join(random_string());
+    fs::create_dir_all(&path).wrap_error(FileOperation::CreateDir, || path.clone())?;
+    Ok(path)
+}
+
+// Matches path against glob pattern; "*" and "?" don't match path separator, "**"
+// matches any number of path components.
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    glob_match_chars(&pattern, &path)
+}
+
+fn glob_match_chars(pattern: &[char], path: &[char]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            // "**/" also matches no component at all
+            let rest = &pattern[2..];
+            if rest.first() == Some(&'/') && glob_match_chars(&rest[1..], path) {
+                return true;
+            }
+            (0..=path.len()).any(|i| glob_match_chars(rest, &path[i..]))
+        }
+        Some('*') => {
+            let rest = &pattern[1..];
+            for i in 0..=path.len() {
+                if glob_match_chars(rest, &path[i..]) {
+                    return true;
+                }
+                if path.get(i) == Some(&'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => match path.first() {
+            Some(c) if *c != '/' => glob_match_chars(&pattern[1..], &path[1..]),
+            _ => false,
+        },
+        Some(c) => path.first() == Some(c) && glob_match_chars(&pattern[1..], &path[1..]),
+    }
+}
+
+// Expands glob pattern into matching files and folders (folders that match are not
+// descended into). Returns the literal folder prefix of the pattern along with
+// matches, so that callers can preserve structure relative to it.
+pub fn expand_glob(pattern: &Path) -> ToolResult<(PathBuf, Vec<PathBuf>)> {
+    let is_wildcard = |c: &Component| {
+        let c = c.as_os_str().to_string_lossy();
+        c.contains('*') || c.contains('?')
+    };
+    let components: Vec<_> = pattern.components().collect();
+    let literal = components.iter().take_while(|c| !is_wildcard(c)).count();
+    if literal == components.len() {
+        let base = pattern.parent().unwrap_or_else(|| Path::new("")).into();
+        let matches = if pattern.exists() {
+            vec![pattern.into()]
+        } else {
+            Vec::new()
+        };
+        return Ok((base, matches));
+    }
+    let base: PathBuf = components[..literal].iter().collect();
+    let rest: PathBuf = components[literal..].iter().collect();
+    let mut matches = Vec::new();
+    if base.as_os_str().is_empty() || base.is_dir() {
+        expand_glob_dir(&base, &base, &rest.to_string_lossy(), &mut matches)?;
+    }
+    Ok((base, matches))
+}
+
+fn expand_glob_dir(
+    base: &Path,
+    dir: &Path,
+    pattern: &str,
+    matches: &mut Vec<PathBuf>,
+) -> ToolResult<()> {
+    let read_dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
     } else {
-        fs::copy(src, dest)?;
+        dir
+    };
+    let mut entries = Vec::new();
+    for entry in read_dir
+        .read_dir()
+        .wrap_error(FileOperation::ReadDir, || dir.into())?
+    {
+        let entry = entry.wrap_error(FileOperation::Read, || dir.into())?;
+        entries.push(dir.join(entry.file_name()));
+    }
+    entries.sort();
+    for entry in entries {
+        let relative = entry.strip_prefix(base).unwrap().to_string_lossy();
+        if glob_match(pattern, &relative) {
+            matches.push(entry);
+        } else if entry.is_dir() {
+            expand_glob_dir(base, &entry, pattern, matches)?;
+        }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Extended attributes of file, or None if filesystem doesn't support them
+    fn xattrs(path: &Path) -> Option<Vec<String>> {
+        let mut res: Vec<_> = ignore_unsupported(xattr::list(path))
+            .unwrap()?
+            .map(|n| n.to_string_lossy().into_owned())
+            .collect();
+        res.sort();
+        Some(res)
+    }
+
+    #[test]
+    fn copies_mode_and_times() {
+        let root = temp_dir().unwrap();
+        let src = root.join("src");
+        fs::write(&src, b"data").unwrap();
+        fs::set_permissions(&src, fs::Permissions::from_mode(0o444)).unwrap();
+        let mtime = FileTime::from_unix_time(1_000_000_000, 0);
+        filetime::set_file_times(&src, mtime, mtime).unwrap();
+
+        let dest = root.join("preserved");
+        Copier::new(CopyOptions::default())
+            .copy_file(&src, &dest)
+            .unwrap();
+        let meta = dest.metadata().unwrap();
+        assert_eq!(meta.mode() & 0o777, 0o444);
+        assert_eq!(FileTime::from_last_modification_time(&meta), mtime);
+
+        let dest = root.join("normalized");
+        Copier::new(CopyOptions {
+            preserve_mode: false,
+            preserve_times: false,
+            ..Default::default()
+        })
+        .copy_file(&src, &dest)
+        .unwrap();
+        let meta = dest.metadata().unwrap();
+        assert_eq!(meta.mode() & 0o777, 0o644);
+        assert_ne!(FileTime::from_last_modification_time(&meta), mtime);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn copies_xattrs() {
+        let root = temp_dir().unwrap();
+        let src = root.join("src");
+        fs::write(&src, b"data").unwrap();
+        if ignore_unsupported(xattr::set(&src, "user.keep", b"1"))
+            .unwrap()
+            .is_none()
+        {
+            return;
+        }
+        xattr::set(&src, "user.strip", b"1").unwrap();
+        fs::set_permissions(&src, fs::Permissions::from_mode(0o444)).unwrap();
+        let strip = CopyOptions {
+            strip_xattrs: vec!["user.strip".into()],
+            ..Default::default()
+        };
+
+        // Read-only source still gets its attributes
+        let dest = root.join("preserved");
+        Copier::new(strip.clone()).copy_file(&src, &dest).unwrap();
+        assert_eq!(xattrs(&dest), Some(vec!["user.keep".into()]));
+        assert_eq!(dest.metadata().unwrap().mode() & 0o777, 0o444);
+
+        // Attributes copied along with content are removed as well
+        let dest = root.join("dropped");
+        fs::write(&dest, b"data").unwrap();
+        xattr::set(&dest, "user.keep", b"1").unwrap();
+        Copier::new(CopyOptions {
+            preserve_xattrs: false,
+            ..strip
+        })
+        .copy_metadata(&src, &dest)
+        .unwrap();
+        assert_eq!(xattrs(&dest), Some(Vec::new()));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn copies_hardlinks() {
+        let root = temp_dir().unwrap();
+        let src = root.join("src");
+        fs::create_dir(&src).unwrap();
+        fs::write(src.join("a"), b"data").unwrap();
+        fs::hard_link(src.join("a"), src.join("b")).unwrap();
+        let ino = |path: &Path| path.metadata().unwrap().ino();
+
+        let dest = root.join("preserved");
+        Copier::new(CopyOptions::default())
+            .copy(&src, &dest)
+            .unwrap();
+        assert_eq!(ino(&dest.join("a")), ino(&dest.join("b")));
+        assert_ne!(ino(&dest.join("a")), ino(&src.join("a")));
+
+        let dest = root.join("separate");
+        Copier::new(CopyOptions {
+            preserve_hardlinks: false,
+            ..Default::default()
+        })
+        .copy(&src, &dest)
+        .unwrap();
+        assert_ne!(ino(&dest.join("a")), ino(&dest.join("b")));
+        assert_eq!(fs::read(dest.join("b")).unwrap(), b"data");
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn glob_matches_literals() {
+        assert!(glob_match("", ""));
+        assert!(glob_match("libfoo.dylib", "libfoo.dylib"));
+        assert!(!glob_match("libfoo.dylib", "libfoo.dylib.1"));
+        assert!(!glob_match("libfoo", ""));
+        assert!(!glob_match("", "libfoo"));
+    }
+
+    #[test]
+    fn glob_matches_wildcards() {
+        assert!(glob_match("*.dylib", "libfoo.dylib"));
+        assert!(glob_match("*.dylib", ".dylib"));
+        assert!(glob_match("lib*.dylib", "lib.dylib"));
+        assert!(!glob_match("*.dylib", "lib/libfoo.dylib"));
+        assert!(glob_match("lib?.so", "liba.so"));
+        assert!(!glob_match("lib?.so", "lib.so"));
+        assert!(!glob_match("a?b", "a/b"));
+        assert!(glob_match("*/*.so", "lib/libfoo.so"));
+        assert!(!glob_match("*/*.so", "lib/sub/libfoo.so"));
+        assert!(glob_match("*", "ab"));
+        assert!(!glob_match("*", "a/b"));
+    }
+
+    #[test]
+    fn glob_matches_recursive_wildcards() {
+        assert!(glob_match("**/*.so", "libfoo.so"));
+        assert!(glob_match("**/*.so", "lib/libfoo.so"));
+        assert!(glob_match("**/*.so", "lib/sub/libfoo.so"));
+        assert!(!glob_match("**/*.so", "lib/libfoo.dylib"));
+        assert!(glob_match("lib/**/foo", "lib/foo"));
+        assert!(glob_match("lib/**/foo", "lib/a/b/foo"));
+        assert!(!glob_match("lib/**/foo", "other/a/foo"));
+        assert!(glob_match("lib/**", "lib/a/b"));
+    }
+
+    // Creates empty files (and their folders) below given root
+    fn create_files(root: &Path, files: &[&str]) {
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+    }
+
+    #[test]
+    fn expands_globs() {
+        let root = temp_dir().unwrap();
+        create_files(
+            &root,
+            &[
+                "assets/a.ttf",
+                "assets/b.ttf",
+                "assets/readme.txt",
+                "assets/sub/c.ttf",
+                "assets/en.lproj/Main.strings",
+                "assets/de.lproj/Main.strings",
+            ],
+        );
+        let assets = root.join("assets");
+
+        let (base, matches) = expand_glob(&assets.join("*.ttf")).unwrap();
+        assert_eq!(base, assets);
+        assert_eq!(matches, vec![assets.join("a.ttf"), assets.join("b.ttf")]);
+
+        let (base, matches) = expand_glob(&assets.join("**/*.ttf")).unwrap();
+        assert_eq!(base, assets);
+        assert_eq!(
+            matches,
+            vec![
+                assets.join("a.ttf"),
+                assets.join("b.ttf"),
+                assets.join("sub/c.ttf")
+            ]
+        );
+
+        // Matching folders are not descended into
+        let (_, matches) = expand_glob(&assets.join("*.lproj")).unwrap();
+        assert_eq!(
+            matches,
+            vec![assets.join("de.lproj"), assets.join("en.lproj")]
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn expands_literal_paths() {
+        let root = temp_dir().unwrap();
+        create_files(&root, &["assets/a.ttf"]);
+        let assets = root.join("assets");
+
+        let (base, matches) = expand_glob(&assets.join("a.ttf")).unwrap();
+        assert_eq!(base, assets);
+        assert_eq!(matches, vec![assets.join("a.ttf")]);
+
+        let (base, matches) = expand_glob(&assets).unwrap();
+        assert_eq!(base, root);
+        assert_eq!(matches, vec![assets.clone()]);
+
+        let (_, matches) = expand_glob(&assets.join("missing.ttf")).unwrap();
+        assert!(matches.is_empty());
+        let (base, matches) = expand_glob(&root.join("missing/*.ttf")).unwrap();
+        assert_eq!(base, root.join("missing"));
+        assert!(matches.is_empty());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}